@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use erased_serde::Deserializer as ErasedDeserializer;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::error::Error;
+
+/// The content type of JSON payloads, which events written before codecs could be chosen
+/// don't record.
+pub(crate) const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Deserializes a payload from the deserializer a codec hands it.
+pub type Visit<'a> =
+    dyn for<'de> FnMut(&mut dyn ErasedDeserializer<'de>) -> Result<(), erased_serde::Error> + 'a;
+
+/// Serializes event payloads to bytes and back.
+///
+/// Codecs work on the event types themselves, through `erased_serde` so they can be chosen at
+/// runtime, and only payloads that must be upcast pass through a `serde_json::Value`. The
+/// content type a payload was written with is recorded alongside it, and readers decode it with
+/// the codec registered for that content type in `Codecs`, regardless of which codec the store
+/// or event type writes with.
+pub trait EventCodec: fmt::Debug + Send + Sync {
+    /// Names the format, and is stored with every event written. It must not change once events
+    /// have been written with it.
+    fn content_type(&self) -> &str;
+
+    fn encode(&self, payload: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error>;
+
+    /// Deserializes the payload in `bytes` by handing `visit` a deserializer over them.
+    fn decode(&self, bytes: &[u8], visit: &mut Visit<'_>) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+/// MessagePack, with struct fields written by name so payloads stay self-describing and can be
+/// upcast.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+impl EventCodec for Json {
+    fn content_type(&self) -> &str {
+        JSON_CONTENT_TYPE
+    }
+
+    fn encode(&self, payload: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(payload)?)
+    }
+
+    fn decode(&self, bytes: &[u8], visit: &mut Visit<'_>) -> Result<(), Error> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        visit(&mut <dyn ErasedDeserializer>::erase(&mut deserializer))
+            .map_err(serde::de::Error::custom)
+            .map_err(Error::EventDeserializationError)?;
+        Ok(deserializer.end()?)
+    }
+}
+
+impl EventCodec for MessagePack {
+    fn content_type(&self) -> &str {
+        "application/msgpack"
+    }
+
+    fn encode(&self, payload: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec_named(payload).map_err(|e| codec_error(self, e))
+    }
+
+    fn decode(&self, bytes: &[u8], visit: &mut Visit<'_>) -> Result<(), Error> {
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(bytes);
+        visit(&mut <dyn ErasedDeserializer>::erase(&mut deserializer))
+            .map_err(|e| codec_error(self, e))
+    }
+}
+
+impl EventCodec for Cbor {
+    fn content_type(&self) -> &str {
+        "application/cbor"
+    }
+
+    fn encode(&self, payload: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        cbor4ii::serde::to_vec(Vec::new(), &payload).map_err(|e| codec_error(self, e))
+    }
+
+    fn decode(&self, bytes: &[u8], visit: &mut Visit<'_>) -> Result<(), Error> {
+        let reader = cbor4ii::core::utils::SliceReader::new(bytes);
+        let mut deserializer = cbor4ii::serde::Deserializer::new(reader);
+        visit(&mut <dyn ErasedDeserializer>::erase(&mut deserializer))
+            .map_err(|e| codec_error(self, e))
+    }
+}
+
+/// The error a codec reports when `source` stops it encoding or decoding a payload.
+fn codec_error(
+    codec: &dyn EventCodec,
+    source: impl std::error::Error + Send + Sync + 'static,
+) -> Error {
+    Error::EventCodec {
+        content_type: codec.content_type().to_string(),
+        source: Box::new(source),
+    }
+}
+
+/// Serializes `value` with `codec`.
+pub(crate) fn encode<T: Serialize>(codec: &dyn EventCodec, value: &T) -> Result<Vec<u8>, Error> {
+    codec.encode(value)
+}
+
+/// Deserializes a payload `codec` encoded.
+pub(crate) fn decode<T: DeserializeOwned>(
+    codec: &dyn EventCodec,
+    bytes: &[u8],
+) -> Result<T, Error> {
+    let mut payload = None;
+    codec.decode(bytes, &mut |deserializer| {
+        payload = Some(erased_serde::deserialize(deserializer)?);
+        Ok(())
+    })?;
+    payload.ok_or_else(|| Error::EventCodec {
+        content_type: codec.content_type().to_string(),
+        source: "the codec decoded no payload".into(),
+    })
+}
+
+/// The codecs events are read back with, keyed by content type.
+///
+/// `Json`, `MessagePack` and `Cbor` are registered from the start. A store also registers the
+/// codec it is configured to write with; codecs that only some event types write with must be
+/// registered here.
+#[derive(Debug, Clone)]
+pub struct Codecs {
+    codecs: HashMap<String, Arc<dyn EventCodec>>,
+}
+
+impl Codecs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `codec` to decode payloads of its content type, replacing any codec registered
+    /// for it before.
+    pub fn register(self, codec: impl EventCodec + 'static) -> Self {
+        self.register_shared(Arc::new(codec))
+    }
+
+    pub(crate) fn register_shared(mut self, codec: Arc<dyn EventCodec>) -> Self {
+        self.codecs.insert(codec.content_type().to_string(), codec);
+        self
+    }
+
+    /// Returns the codec registered for `content_type`.
+    pub fn get(&self, content_type: &str) -> Result<&dyn EventCodec, Error> {
+        self.codecs
+            .get(content_type)
+            .map(|codec| codec.as_ref())
+            .ok_or_else(|| Error::EventCodec {
+                content_type: content_type.to_string(),
+                source: "no codec for this content type".into(),
+            })
+    }
+
+    /// Decodes a payload recorded with `content_type`, or as JSON if it recorded none.
+    pub(crate) fn decode<T: DeserializeOwned>(
+        &self,
+        content_type: Option<&str>,
+        bytes: &[u8],
+    ) -> Result<T, Error> {
+        decode(self.get(content_type.unwrap_or(JSON_CONTENT_TYPE))?, bytes)
+    }
+}
+
+impl Default for Codecs {
+    fn default() -> Self {
+        Self {
+            codecs: HashMap::new(),
+        }
+        .register(Json)
+        .register(MessagePack)
+        .register(Cbor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::Value;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Reading {
+        Taken { sensor: String, value: f64 },
+    }
+
+    fn reading() -> Reading {
+        Reading::Taken {
+            sensor: "t-1".to_string(),
+            value: 21.5,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_every_codec() {
+        let codecs = Codecs::new();
+        for codec in [&Json as &dyn EventCodec, &MessagePack, &Cbor] {
+            let bytes = encode(codec, &reading()).unwrap();
+            let decoder = codecs.get(codec.content_type()).unwrap();
+            assert_eq!(decode::<Reading>(decoder, &bytes).unwrap(), reading());
+        }
+    }
+
+    #[test]
+    fn binary_payloads_decode_to_named_json_values() {
+        for codec in [&MessagePack as &dyn EventCodec, &Cbor] {
+            let bytes = encode(codec, &reading()).unwrap();
+            assert_eq!(
+                decode::<Value>(codec, &bytes).unwrap(),
+                serde_json::json!({ "Taken": { "sensor": "t-1", "value": 21.5 } })
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_with_registered_codecs() {
+        #[derive(Debug)]
+        struct Reversed;
+
+        impl EventCodec for Reversed {
+            fn content_type(&self) -> &str {
+                "application/x-reversed-json"
+            }
+
+            fn encode(&self, payload: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+                let mut bytes = serde_json::to_vec(payload)?;
+                bytes.reverse();
+                Ok(bytes)
+            }
+
+            fn decode(&self, bytes: &[u8], visit: &mut Visit<'_>) -> Result<(), Error> {
+                let mut bytes = bytes.to_vec();
+                bytes.reverse();
+                Json.decode(&bytes, visit)
+            }
+        }
+
+        let bytes = encode(&Reversed, &reading()).unwrap();
+        assert!(matches!(
+            Codecs::new().decode::<Reading>(Some(Reversed.content_type()), &bytes),
+            Err(Error::EventCodec { .. })
+        ));
+        let codecs = Codecs::new().register(Reversed);
+        assert_eq!(
+            codecs
+                .decode::<Reading>(Some(Reversed.content_type()), &bytes)
+                .unwrap(),
+            reading()
+        );
+        assert_eq!(
+            codecs
+                .decode::<Reading>(None, &encode(&Json, &reading()).unwrap())
+                .unwrap(),
+            reading()
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use crate::EventStreamVersion;
+use crate::context::CommandContext;
+use crate::error::Error;
 use crate::event::Event;
 use crate::event_store::EventStreamId;
+use crate::metadata::EventMetadata;
 use std::fmt::Debug;
 
 pub trait Command: Clone {
@@ -16,6 +19,76 @@ pub trait Command: Clone {
 
     fn set_state(&mut self, state: &Self::State);
 
+    /// Metadata recorded with every event the command emits, such as the correlation id of the
+    /// request being handled or `EventMetadata::caused_by` an event the command reacts to.
+    /// Returning an error, such as from `EventMetadata::with_custom`, fails the command before
+    /// anything is appended.
+    fn metadata(&self) -> Result<EventMetadata, Error> {
+        Ok(EventMetadata::default())
+    }
+
+    /// A key identifying the business operation the command performs, such as the id of the
+    /// request it came from. The ids of the events it emits are derived from the key, and if
+    /// `execute` finds events already appended under it, the command is not handled again:
+    /// `execute` returns the outcome of the execution that appended them instead.
+    fn idempotency_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Prepares the command for another attempt after its events lost a race with another
+    /// writer. It is called on the command as it was passed to `execute`, before any events
+    /// were applied, and every attempt rebuilds the state on a clone of the result.
+    fn mark_retry(&self) -> Self
+    where
+        Self: Sized + Clone,
+    {
+        self.clone()
+    }
+
+    fn override_expected_version(&self) -> Option<EventStreamVersion> {
+        None
+    }
+
+    fn apply(&mut self, event: &Self::Event)
+    where
+        Self: Sized,
+    {
+        self.set_state(self.get_state().apply(event));
+    }
+}
+
+/// A command whose handler is asynchronous and can call out to the services in a
+/// `CommandContext`, such as a pricing service or a uniqueness index. `execute_async` runs it
+/// with the same retry semantics as a `Command`, calling `handle` again on every attempt.
+pub trait AsyncCommand: Clone {
+    type Event: Event;
+    type State: AggregateState<Self::Event>;
+    type Error: std::error::Error + Send + Sync + 'static;
+    /// The services handed to `handle` through its context.
+    type Services;
+
+    fn handle(
+        &self,
+        context: &CommandContext<Self::Services>,
+    ) -> impl std::future::Future<Output = Result<Vec<Self::Event>, Self::Error>> + Send;
+
+    fn event_stream_id(&self) -> EventStreamId;
+
+    fn get_state(&self) -> Self::State;
+
+    fn set_state(&mut self, state: &Self::State);
+
+    /// Metadata recorded with every event the command emits, as for `Command::metadata`.
+    fn metadata(&self) -> Result<EventMetadata, Error> {
+        Ok(EventMetadata::default())
+    }
+
+    /// The key that identifies repeats of the command, as for `Command::idempotency_key`.
+    fn idempotency_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Prepares the command for another attempt, as for `Command::mark_retry`.
     fn mark_retry(&self) -> Self
     where
         Self: Sized + Clone,
@@ -35,6 +108,57 @@ pub trait Command: Clone {
     }
 }
 
+/// Runs a `Command` as an `AsyncCommand`, so `execute` and `execute_async` share one retry loop.
+#[derive(Clone)]
+pub(crate) struct Synchronous<C>(pub(crate) C);
+
+impl<C: Command> AsyncCommand for Synchronous<C> {
+    type Event = C::Event;
+    type State = C::State;
+    type Error = C::Error;
+    type Services = ();
+
+    fn handle(
+        &self,
+        _: &CommandContext<()>,
+    ) -> impl std::future::Future<Output = Result<Vec<Self::Event>, Self::Error>> + Send {
+        // Handled before the future is created, so it needn't hold a reference to the command.
+        std::future::ready(self.0.handle())
+    }
+
+    fn event_stream_id(&self) -> EventStreamId {
+        self.0.event_stream_id()
+    }
+
+    fn get_state(&self) -> Self::State {
+        self.0.get_state()
+    }
+
+    fn set_state(&mut self, state: &Self::State) {
+        self.0.set_state(state)
+    }
+
+    fn metadata(&self) -> Result<EventMetadata, Error> {
+        self.0.metadata()
+    }
+
+    fn idempotency_key(&self) -> Option<String> {
+        self.0.idempotency_key()
+    }
+
+    fn mark_retry(&self) -> Self {
+        Self(self.0.mark_retry())
+    }
+
+    fn override_expected_version(&self) -> Option<EventStreamVersion> {
+        self.0.override_expected_version()
+    }
+
+    fn apply(&mut self, event: &Self::Event) {
+        self.0.apply(event)
+    }
+}
+
 pub trait AggregateState<E: Event>: Debug + Sized {
     fn apply(&mut self, event: &E) -> &Self;
 }
@@ -1,14 +1,26 @@
-use crate::delay::RetryDelay;
+use std::sync::Arc;
+
+use crate::dedupe::DedupeIndex;
+use crate::delay::{ExponentialBackoff, RetryDelay, RetryPolicy};
 use crate::error::Error;
+use crate::upcast::Upcasters;
 
 const MAX_RETRIES_LIMIT: u32 = 10;
 const MIN_DELAY_MS: u64 = 50;
 const MAX_DELAY_MS: u64 = 5000;
+const DEFAULT_SNAPSHOT_FREQUENCY: u64 = 100;
 
 #[derive(Debug, Clone)]
 pub struct ExecuteConfig {
     max_retries: u32,
     retry_delay: RetryDelay,
+    retry_policy: Option<Arc<dyn RetryPolicy>>,
+    rng_seed: Option<u64>,
+    retry_transient_errors: bool,
+    idempotency_key: Option<String>,
+    dedupe_index: Option<DedupeIndex>,
+    snapshot_frequency: u64,
+    upcasters: Upcasters,
 }
 
 impl ExecuteConfig {
@@ -67,13 +79,111 @@ impl ExecuteConfig {
         Ok(self)
     }
 
+    /// Retries commands according to `policy`, in place of the exponential backoff configured by
+    /// `with_max_retries`, `with_base_delay` and `with_max_delay`. The policy is shared by every
+    /// command executed with this config and its clones.
+    pub fn with_retry_policy(mut self, policy: impl RetryPolicy + 'static) -> Self {
+        self.retry_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Retries reads and appends that fail with an `Error::is_transient` error, backing off
+    /// according to the retry policy. A failed append is retried with the same events and
+    /// event ids, so a write that was committed before the failure isn't recorded twice.
+    pub fn with_transient_retries(mut self, retry: bool) -> Self {
+        self.retry_transient_errors = retry;
+        self
+    }
+
+    /// Records where the events of commands with an idempotency key were appended in `index`,
+    /// so that repeats are recognized even when their events precede the snapshot the command
+    /// is restored from.
+    pub fn with_dedupe_index(mut self, index: DedupeIndex) -> Self {
+        self.dedupe_index = Some(index);
+        self
+    }
+
+    /// Derives the ids of the events the command appends from `key` and each event's index,
+    /// rather than generating random ones. An append of the same events that is repeated after
+    /// it was committed, say by a retry after a timeout, is then a no-op instead of a duplicate.
+    ///
+    /// The key only derives event ids for commands that don't have an idempotency key of their
+    /// own. Unlike `Command::idempotency_key`, it doesn't make `execute` recognize a command it
+    /// already handled, so a config reused for several commands doesn't turn the later ones into
+    /// duplicates.
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Seeds the RNG that jitters retry delays, so the delays of each execution are the same
+    /// from run to run. Meant for tests; by default every thread's RNG is seeded from the OS.
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Sets how many events `execute_with_snapshots` lets accumulate after a snapshot before
+    /// saving a new one.
+    pub fn with_snapshot_frequency(mut self, events: u64) -> Result<Self, Error> {
+        if events == 0 {
+            return Err(Error::InvalidConfig {
+                message: "snapshot_frequency cannot be 0".to_string(),
+                parameter: Some("snapshot_frequency".to_string()),
+            });
+        }
+        self.snapshot_frequency = events;
+        Ok(self)
+    }
+
+    /// Sets the upcasters applied to the command's stream as it is read.
+    pub fn with_upcasters(mut self, upcasters: Upcasters) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
     pub fn max_retries(&self) -> u32 {
         self.max_retries
     }
 
+    pub fn snapshot_frequency(&self) -> u64 {
+        self.snapshot_frequency
+    }
+
     pub fn retry_delay(&self) -> &RetryDelay {
         &self.retry_delay
     }
+
+    pub fn retries_transient_errors(&self) -> bool {
+        self.retry_transient_errors
+    }
+
+    pub fn rng_seed(&self) -> Option<u64> {
+        self.rng_seed
+    }
+
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    pub fn dedupe_index(&self) -> Option<&DedupeIndex> {
+        self.dedupe_index.as_ref()
+    }
+
+    /// The policy `execute` retries commands with.
+    pub fn retry_policy(&self) -> Arc<dyn RetryPolicy> {
+        match &self.retry_policy {
+            Some(policy) => policy.clone(),
+            None => Arc::new(ExponentialBackoff::from_delay(
+                self.retry_delay,
+                self.max_retries,
+            )),
+        }
+    }
+
+    pub fn upcasters(&self) -> &Upcasters {
+        &self.upcasters
+    }
 }
 
 impl Default for ExecuteConfig {
@@ -81,6 +191,13 @@ impl Default for ExecuteConfig {
         Self {
             max_retries: 3,
             retry_delay: RetryDelay::default(),
+            retry_policy: None,
+            rng_seed: None,
+            retry_transient_errors: false,
+            idempotency_key: None,
+            dedupe_index: None,
+            snapshot_frequency: DEFAULT_SNAPSHOT_FREQUENCY,
+            upcasters: Upcasters::default(),
         }
     }
 }
@@ -187,6 +304,24 @@ mod tests {
         assert_eq!(config.retry_delay().max_delay_ms(), 1000);
     }
 
+    #[test]
+    fn validates_snapshot_frequency() {
+        match ExecuteConfig::default().with_snapshot_frequency(0) {
+            Err(Error::InvalidConfig {
+                message, parameter, ..
+            }) => {
+                assert_eq!(message, "snapshot_frequency cannot be 0");
+                assert_eq!(parameter, Some("snapshot_frequency".to_string()));
+            }
+            other => panic!("Expected InvalidConfig error, got {:?}", other),
+        }
+
+        let config = ExecuteConfig::default()
+            .with_snapshot_frequency(10)
+            .expect("Failed to set valid snapshot_frequency");
+        assert_eq!(config.snapshot_frequency(), 10);
+    }
+
     #[test]
     fn default_values_are_valid() {
         let config = ExecuteConfig::default();
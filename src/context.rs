@@ -0,0 +1,88 @@
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+type Clock = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+type IdGenerator = Arc<dyn Fn() -> Uuid + Send + Sync>;
+
+/// What an `AsyncCommand` can reach while it is handled: the services it was given, a clock and
+/// an id generator.
+///
+/// Handlers that read the time and generate ids through the context rather than directly can be
+/// tested with a fixed clock and predictable ids. Clones share the same clock and id generator.
+#[derive(Clone)]
+pub struct CommandContext<S> {
+    services: S,
+    clock: Clock,
+    ids: IdGenerator,
+}
+
+impl<S> CommandContext<S> {
+    /// Wraps `services` with the system clock and random v4 ids.
+    pub fn new(services: S) -> Self {
+        Self {
+            services,
+            clock: Arc::new(Utc::now),
+            ids: Arc::new(Uuid::new_v4),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Fn() -> DateTime<Utc> + Send + Sync + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn with_id_generator(mut self, ids: impl Fn() -> Uuid + Send + Sync + 'static) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+
+    pub fn services(&self) -> &S {
+        &self.services
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        (self.clock)()
+    }
+
+    pub fn new_id(&self) -> Uuid {
+        (self.ids)()
+    }
+}
+
+impl<S: Default> Default for CommandContext<S> {
+    fn default() -> Self {
+        Self::new(S::default())
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for CommandContext<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandContext")
+            .field("services", &self.services)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn uses_the_clock_and_ids_it_is_given() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let next = Arc::new(AtomicU64::new(1));
+        let context = CommandContext::new("services")
+            .with_clock(move || now)
+            .with_id_generator(move || Uuid::from_u128(next.fetch_add(1, Ordering::SeqCst).into()));
+
+        assert_eq!(context.services(), &"services");
+        assert_eq!(context.now(), now);
+        assert_eq!(context.new_id(), Uuid::from_u128(1));
+        assert_eq!(context.clone().new_id(), Uuid::from_u128(2));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::event_store::{EventStreamId, EventStreamVersion, GlobalPosition};
+use crate::new_event::idempotent_id;
+use crate::outcome::ExecuteOutcome;
+use crate::recorded_event::RecordedEvent;
+
+/// Remembers where the events of commands with an idempotency key were appended, for as long as
+/// its retention window.
+///
+/// `execute` recognizes a repeated command by finding the events appended under its key as it
+/// replays the stream, but a command restored from a snapshot only replays the events after the
+/// snapshot. With an index in its `ExecuteConfig`, `execute` records where each keyed command's
+/// events went and reads them back from there when the replay didn't reach them. Clones share
+/// the same index.
+#[derive(Debug, Clone)]
+pub struct DedupeIndex {
+    retention: Duration,
+    entries: Arc<Mutex<HashMap<(EventStreamId, String), Entry>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    first_version: EventStreamVersion,
+    count: usize,
+    recorded: Instant,
+}
+
+impl DedupeIndex {
+    /// Creates an empty index that forgets each key `retention` after recording it.
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            entries: Default::default(),
+        }
+    }
+
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    /// Records that `count` events were appended under `key` from `first_version` on, and
+    /// forgets the keys whose retention window has passed.
+    pub(crate) fn record(
+        &self,
+        stream_id: &EventStreamId,
+        key: &str,
+        first_version: EventStreamVersion,
+        count: usize,
+    ) {
+        let mut entries = self.entries.lock().expect("dedupe index lock poisoned");
+        entries.retain(|_, entry| entry.recorded.elapsed() < self.retention);
+        entries.insert(
+            (stream_id.clone(), key.to_string()),
+            Entry {
+                first_version,
+                count,
+                recorded: Instant::now(),
+            },
+        );
+    }
+
+    /// The version of the first event appended under `key` and how many there were, if they
+    /// were recorded within the retention window.
+    pub(crate) fn lookup(
+        &self,
+        stream_id: &EventStreamId,
+        key: &str,
+    ) -> Option<(EventStreamVersion, usize)> {
+        let entries = self.entries.lock().expect("dedupe index lock poisoned");
+        entries
+            .get(&(stream_id.clone(), key.to_string()))
+            .filter(|entry| entry.recorded.elapsed() < self.retention)
+            .map(|entry| (entry.first_version, entry.count))
+    }
+}
+
+/// The events an earlier execution appended under an idempotency key, as they are read back
+/// from the stream.
+pub(crate) struct KeyedEvents<E> {
+    key: String,
+    events: Vec<(E, EventStreamVersion)>,
+    position: Option<GlobalPosition>,
+}
+
+impl<E> KeyedEvents<E> {
+    pub(crate) fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            events: Vec::new(),
+            position: None,
+        }
+    }
+
+    /// Collects `recorded` if it has the id of the next event appended under the key.
+    pub(crate) fn observe(&mut self, recorded: RecordedEvent<E>) -> bool {
+        if recorded.id() != idempotent_id(&self.key, self.events.len()) {
+            return false;
+        }
+        self.position = Some(recorded.position());
+        let version = recorded.version();
+        self.events.push((recorded.into_event(), version));
+        true
+    }
+
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The outcome of the earlier execution, reported with no attempts and the aggregate's
+    /// current `state`.
+    pub(crate) fn into_outcome<S>(self, state: S) -> ExecuteOutcome<E, S> {
+        let version = self.events.last().map(|&(_, version)| version);
+        ExecuteOutcome::new(self.events, version, self.position, 0, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_keys_after_the_retention_window() {
+        let stream_id = EventStreamId::new();
+        let version = EventStreamVersion::new(3);
+
+        let index = DedupeIndex::new(Duration::from_secs(60));
+        index.record(&stream_id, "key", version, 2);
+        assert_eq!(index.lookup(&stream_id, "key"), Some((version, 2)));
+        assert_eq!(index.lookup(&stream_id, "other key"), None);
+        assert_eq!(index.lookup(&EventStreamId::new(), "key"), None);
+
+        let index = DedupeIndex::new(Duration::ZERO);
+        index.record(&stream_id, "key", version, 2);
+        assert_eq!(index.lookup(&stream_id, "key"), None);
+    }
+}
@@ -1,9 +1,342 @@
 use rand::prelude::*;
 use std::cell::RefCell;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::time::Duration;
 
 thread_local! {
-    static THREAD_RNG: RefCell<SmallRng> = RefCell::new(SmallRng::seed_from_u64(0));
+    // Seeded per thread from the OS, so processes and threads retrying at once don't jitter in
+    // lockstep.
+    static THREAD_RNG: RefCell<JitterRng> = RefCell::new(JitterRng::seeded(entropy_seed()));
+}
+
+fn entropy_seed() -> u64 {
+    getrandom::u64().unwrap_or_else(|_| {
+        // Only reachable if the OS has no entropy source; the time still differs between
+        // processes.
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+    })
+}
+
+/// The random numbers retry policies jitter their delays with.
+pub trait Jitter {
+    /// Returns a number drawn uniformly from `low..=high`.
+    fn between(&mut self, low: u64, high: u64) -> u64;
+}
+
+/// The generator `execute` draws jitter from.
+pub(crate) struct JitterRng(SmallRng);
+
+impl JitterRng {
+    pub(crate) fn seeded(seed: u64) -> Self {
+        Self(SmallRng::seed_from_u64(seed))
+    }
+}
+
+impl Jitter for JitterRng {
+    fn between(&mut self, low: u64, high: u64) -> u64 {
+        self.0.random_range(low..=high)
+    }
+}
+
+/// The retries made by one execution of a command.
+pub(crate) struct Retries {
+    policy: Arc<dyn RetryPolicy>,
+    // Jitters delays when `ExecuteConfig` was given a seed; the thread's RNG does otherwise.
+    seeded_rng: Option<JitterRng>,
+    count: u32,
+    transient: u32,
+    previous_delay: Option<Duration>,
+}
+
+impl Retries {
+    pub(crate) fn new(policy: Arc<dyn RetryPolicy>, seed: Option<u64>) -> Self {
+        Self {
+            policy,
+            seeded_rng: seed.map(JitterRng::seeded),
+            count: 0,
+            transient: 0,
+            previous_delay: None,
+        }
+    }
+
+    /// How many of the retries followed a transient error.
+    pub(crate) fn transient(&self) -> u32 {
+        self.transient
+    }
+
+    /// The policy's limit on retries, or the number it allowed if it has no fixed limit.
+    pub(crate) fn max_retries(&self) -> u32 {
+        self.policy.max_retries().unwrap_or(self.count)
+    }
+
+    /// Like `back_off`, but counts the retry as one that followed a transient error.
+    pub(crate) async fn back_off_transient(&mut self) -> bool {
+        let retrying = self.back_off().await;
+        if retrying {
+            self.transient += 1;
+        }
+        retrying
+    }
+
+    /// Waits out the policy's delay before another retry, or returns `false` without waiting
+    /// if the policy gives up.
+    pub(crate) async fn back_off(&mut self) -> bool {
+        let (retry, previous) = (self.count, self.previous_delay);
+        let delay = match &mut self.seeded_rng {
+            Some(rng) => self.policy.next_delay(retry, previous, rng),
+            None => THREAD_RNG.with(|rng| {
+                self.policy
+                    .next_delay(retry, previous, &mut *rng.borrow_mut())
+            }),
+        };
+        let Some(delay) = delay else {
+            return false;
+        };
+        tokio::time::sleep(delay).await;
+        self.previous_delay = Some(delay);
+        self.count += 1;
+        true
+    }
+}
+
+/// Decides whether `execute` retries a command, and how long it waits first. It governs both
+/// retries after the command's events lost a race with another writer (a version mismatch) and,
+/// when `ExecuteConfig::with_transient_retries` is on, retries of reads and appends that failed
+/// with a transient error. Both kinds count towards the same retry numbers and limit.
+pub trait RetryPolicy: Debug + Send + Sync {
+    /// Returns the delay before retry number `retry`, counting from 0, or `None` to give up.
+    /// `previous` is the delay waited before the last retry, if there was one. Any jitter should
+    /// be drawn from `jitter`, which is seeded by `ExecuteConfig::with_rng_seed` when one is set.
+    fn next_delay(
+        &self,
+        retry: u32,
+        previous: Option<Duration>,
+        jitter: &mut dyn Jitter,
+    ) -> Option<Duration>;
+
+    /// The most retries the policy ever allows, if it has a fixed limit. Reported by
+    /// `Error::MaxRetriesExceeded`.
+    fn max_retries(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Exponential backoff with full jitter: each delay is random, up to the base delay doubled
+/// for every earlier retry and capped at the maximum. This is the default policy.
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialBackoff {
+    delay: RetryDelay,
+    max_retries: u32,
+}
+
+impl ExponentialBackoff {
+    pub fn new(base: Duration, max: Duration, max_retries: u32) -> Self {
+        Self {
+            delay: RetryDelay::new(base.as_millis() as u64, max.as_millis() as u64),
+            max_retries,
+        }
+    }
+
+    pub(crate) fn from_delay(delay: RetryDelay, max_retries: u32) -> Self {
+        Self { delay, max_retries }
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn next_delay(
+        &self,
+        retry: u32,
+        _: Option<Duration>,
+        jitter: &mut dyn Jitter,
+    ) -> Option<Duration> {
+        (retry < self.max_retries).then(|| self.delay.jittered_delay(retry, jitter))
+    }
+
+    fn max_retries(&self) -> Option<u32> {
+        Some(self.max_retries)
+    }
+}
+
+/// Waits the same delay before every retry.
+#[derive(Debug, Clone, Copy)]
+pub struct ConstantBackoff {
+    delay: Duration,
+    max_retries: u32,
+}
+
+impl ConstantBackoff {
+    pub fn new(delay: Duration, max_retries: u32) -> Self {
+        Self { delay, max_retries }
+    }
+}
+
+impl RetryPolicy for ConstantBackoff {
+    fn next_delay(&self, retry: u32, _: Option<Duration>, _: &mut dyn Jitter) -> Option<Duration> {
+        (retry < self.max_retries).then_some(self.delay)
+    }
+
+    fn max_retries(&self) -> Option<u32> {
+        Some(self.max_retries)
+    }
+}
+
+/// Waits `step` longer before each retry than the one before it, up to `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct LinearBackoff {
+    step: Duration,
+    max: Duration,
+    max_retries: u32,
+}
+
+impl LinearBackoff {
+    pub fn new(step: Duration, max: Duration, max_retries: u32) -> Self {
+        Self {
+            step,
+            max,
+            max_retries,
+        }
+    }
+}
+
+impl RetryPolicy for LinearBackoff {
+    fn next_delay(&self, retry: u32, _: Option<Duration>, _: &mut dyn Jitter) -> Option<Duration> {
+        (retry < self.max_retries).then(|| self.step.saturating_mul(retry + 1).min(self.max))
+    }
+
+    fn max_retries(&self) -> Option<u32> {
+        Some(self.max_retries)
+    }
+}
+
+/// "Decorrelated jitter": each delay is random, between the base delay and three times the
+/// previous delay, capped at the maximum. Spreads out writers contending for a hot stream more
+/// evenly than full jitter.
+#[derive(Debug, Clone, Copy)]
+pub struct DecorrelatedJitter {
+    base: Duration,
+    max: Duration,
+    max_retries: u32,
+}
+
+impl DecorrelatedJitter {
+    pub fn new(base: Duration, max: Duration, max_retries: u32) -> Self {
+        Self {
+            base,
+            max,
+            max_retries,
+        }
+    }
+}
+
+impl RetryPolicy for DecorrelatedJitter {
+    fn next_delay(
+        &self,
+        retry: u32,
+        previous: Option<Duration>,
+        jitter: &mut dyn Jitter,
+    ) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+        let base = self.base.as_millis() as u64;
+        let previous = previous.map_or(base, |d| d.as_millis() as u64);
+        let delay = jitter.between(base, previous.saturating_mul(3).max(base));
+        Some(Duration::from_millis(delay).min(self.max))
+    }
+
+    fn max_retries(&self) -> Option<u32> {
+        Some(self.max_retries)
+    }
+}
+
+/// Limits how many retries every command sharing the budget may make between them, on top of
+/// the limits of the policy it wraps. Each retry spends one of `capacity` tokens, and a token is
+/// returned every `refill_interval`. When the budget is spent, commands that lose a race fail
+/// instead of retrying, which keeps a burst of contention from turning into a retry storm.
+///
+/// Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct RetryBudget<P> {
+    policy: P,
+    capacity: u32,
+    refill_interval: Duration,
+    state: Arc<Mutex<BudgetState>>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    tokens: u32,
+    refilled_at: Instant,
+}
+
+impl<P: RetryPolicy> RetryBudget<P> {
+    pub fn new(policy: P, capacity: u32, refill_interval: Duration) -> Self {
+        Self {
+            policy,
+            capacity,
+            refill_interval,
+            state: Arc::new(Mutex::new(BudgetState {
+                tokens: capacity,
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// The number of retries left in the budget.
+    pub fn remaining(&self) -> u32 {
+        let mut state = self.state.lock().expect("retry budget lock poisoned");
+        self.refill(&mut state);
+        state.tokens
+    }
+
+    fn refill(&self, state: &mut BudgetState) {
+        if self.refill_interval.is_zero() {
+            state.tokens = self.capacity;
+            return;
+        }
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(state.refilled_at);
+        let refills =
+            u32::try_from(elapsed.as_nanos() / self.refill_interval.as_nanos()).unwrap_or(u32::MAX);
+        if refills == 0 {
+            return;
+        }
+        state.tokens = state.tokens.saturating_add(refills).min(self.capacity);
+        // A full budget banks no further refills, so after a long idle spell it starts counting
+        // again from now rather than from however many intervals have passed.
+        state.refilled_at = if state.tokens == self.capacity {
+            now
+        } else {
+            state.refilled_at + self.refill_interval * refills
+        };
+    }
+}
+
+impl<P: RetryPolicy> RetryPolicy for RetryBudget<P> {
+    fn next_delay(
+        &self,
+        retry: u32,
+        previous: Option<Duration>,
+        jitter: &mut dyn Jitter,
+    ) -> Option<Duration> {
+        let delay = self.policy.next_delay(retry, previous, jitter)?;
+        let mut state = self.state.lock().expect("retry budget lock poisoned");
+        self.refill(&mut state);
+        if state.tokens == 0 {
+            return None;
+        }
+        state.tokens -= 1;
+        Some(delay)
+    }
+
+    /// The wrapped policy's limit. The budget may run out first.
+    fn max_retries(&self) -> Option<u32> {
+        self.policy.max_retries()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -29,17 +362,21 @@ impl RetryDelay {
     }
 
     pub fn calculate_delay(&self, retry_count: u32) -> Duration {
-        // Calculate exponential delay
-        let exp_delay = self.base_delay_ms * 2u64.pow(retry_count);
+        THREAD_RNG.with(|rng| self.jittered_delay(retry_count, &mut *rng.borrow_mut()))
+    }
+
+    fn jittered_delay(&self, retry_count: u32, jitter: &mut dyn Jitter) -> Duration {
+        // Calculate exponential delay, saturating once it no longer fits
+        let exp_delay = 2u64
+            .checked_pow(retry_count)
+            .and_then(|factor| self.base_delay_ms.checked_mul(factor))
+            .unwrap_or(u64::MAX);
 
         // Cap at max delay
         let capped_delay = exp_delay.min(self.max_delay_ms);
 
-        // Apply full jitter using thread-local RNG
-        let jittered_delay = THREAD_RNG.with(|rng| {
-            #[allow(deprecated)]
-            rng.borrow_mut().gen_range(0..=capped_delay)
-        });
+        // Apply full jitter
+        let jittered_delay = jitter.between(0, capped_delay);
 
         Duration::from_millis(jittered_delay)
     }
@@ -114,6 +451,128 @@ mod tests {
         );
     }
 
+    #[test]
+    fn policies_give_up_after_max_retries() {
+        let mut rng = JitterRng::seeded(1);
+        let policies: Vec<Box<dyn RetryPolicy>> = vec![
+            Box::new(ExponentialBackoff::new(
+                Duration::from_millis(10),
+                Duration::from_millis(100),
+                2,
+            )),
+            Box::new(ConstantBackoff::new(Duration::from_millis(10), 2)),
+            Box::new(LinearBackoff::new(
+                Duration::from_millis(10),
+                Duration::from_millis(100),
+                2,
+            )),
+            Box::new(DecorrelatedJitter::new(
+                Duration::from_millis(10),
+                Duration::from_millis(100),
+                2,
+            )),
+        ];
+        for policy in policies {
+            assert!(policy.next_delay(0, None, &mut rng).is_some(), "{policy:?}");
+            assert!(policy.next_delay(1, None, &mut rng).is_some(), "{policy:?}");
+            assert_eq!(policy.next_delay(2, None, &mut rng), None, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn linear_backoff_grows_by_step_up_to_max() {
+        let mut rng = JitterRng::seeded(1);
+        let policy = LinearBackoff::new(Duration::from_millis(30), Duration::from_millis(100), 5);
+        let delays: Vec<_> = (0..5)
+            .map(|retry| {
+                policy
+                    .next_delay(retry, None, &mut rng)
+                    .unwrap()
+                    .as_millis()
+            })
+            .collect();
+        assert_eq!(delays, vec![30, 60, 90, 100, 100]);
+    }
+
+    #[test]
+    fn decorrelated_jitter_stays_between_base_and_three_times_previous() {
+        let mut rng = JitterRng::seeded(1);
+        let policy =
+            DecorrelatedJitter::new(Duration::from_millis(10), Duration::from_millis(1000), 100);
+        let mut previous = None;
+        for retry in 0..100 {
+            let delay = policy.next_delay(retry, previous, &mut rng).unwrap();
+            let upper = previous.unwrap_or(Duration::from_millis(10)) * 3;
+            assert!(delay >= Duration::from_millis(10));
+            assert!(delay <= upper.min(Duration::from_millis(1000)));
+            previous = Some(delay);
+        }
+    }
+
+    #[test]
+    fn retry_budget_is_shared_between_clones() {
+        let mut rng = JitterRng::seeded(1);
+        let budget = RetryBudget::new(
+            ConstantBackoff::new(Duration::from_millis(1), 10),
+            3,
+            Duration::from_secs(3600),
+        );
+        let other = budget.clone();
+
+        assert!(budget.next_delay(0, None, &mut rng).is_some());
+        assert!(other.next_delay(0, None, &mut rng).is_some());
+        assert!(budget.next_delay(1, None, &mut rng).is_some());
+        assert_eq!(other.remaining(), 0);
+        assert_eq!(other.next_delay(1, None, &mut rng), None);
+    }
+
+    #[test]
+    fn retry_budget_refills_after_more_intervals_than_fit_in_u32() {
+        let budget = RetryBudget::new(
+            ConstantBackoff::new(Duration::from_millis(1), 10),
+            2,
+            Duration::from_nanos(1),
+        );
+        {
+            let mut state = budget.state.lock().unwrap();
+            state.tokens = 0;
+            state.refilled_at = Instant::now() - Duration::from_secs(10);
+        }
+
+        assert_eq!(budget.remaining(), 2);
+    }
+
+    #[test]
+    fn seeded_rngs_repeat_their_jitter() {
+        let policy =
+            DecorrelatedJitter::new(Duration::from_millis(10), Duration::from_secs(10), 20);
+        let delays = |seed| {
+            let mut rng = JitterRng::seeded(seed);
+            let mut previous = None;
+            (0..20)
+                .map(|retry| {
+                    previous = policy.next_delay(retry, previous, &mut rng);
+                    previous
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(delays(7), delays(7));
+        assert_ne!(delays(7), delays(8));
+    }
+
+    #[test]
+    fn threads_jitter_independently() {
+        let retry_delay = RetryDelay::new(100, 100_000);
+        let sample = move || {
+            (0..20)
+                .map(|_| retry_delay.calculate_delay(10))
+                .collect::<Vec<_>>()
+        };
+        let first = std::thread::spawn(sample).join().unwrap();
+        let second = std::thread::spawn(sample).join().unwrap();
+        assert_ne!(first, second);
+    }
+
     #[test]
     fn respects_max_delay() {
         let retry_delay = RetryDelay::new(100, 500);
@@ -126,4 +585,16 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn exponential_backoff_saturates_at_large_retry_counts() {
+        let mut rng = JitterRng::seeded(1);
+        let policy =
+            ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(5), u32::MAX);
+
+        for retry in [57, 58, 63, 64, 1000, u32::MAX - 1] {
+            let delay = policy.next_delay(retry, None, &mut rng).unwrap();
+            assert!(delay <= Duration::from_secs(5));
+        }
+    }
 }
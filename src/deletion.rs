@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::event_store::{EventStreamId, EventStreamVersion};
+
+/// What `delete_stream`, `tombstone_stream` and `truncate_stream` have done to a stream, for
+/// the adapters that carry out deletions themselves rather than delegating them to Kurrent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Deletion {
+    /// The first version that is still read. Earlier events are discarded.
+    pub(crate) truncate_before: u64,
+    /// Soft-deleted. The stream stays deleted until it is recreated by appending events, which
+    /// are then the first not to be discarded.
+    pub(crate) deleted: bool,
+    pub(crate) tombstoned: bool,
+}
+
+impl Deletion {
+    /// Fails with `Error::EventStoreStreamDeleted` if the stream was tombstoned.
+    pub(crate) fn ensure_not_tombstoned(&self, stream_id: &EventStreamId) -> Result<(), Error> {
+        if self.tombstoned {
+            return Err(Error::EventStoreStreamDeleted(stream_id.clone()));
+        }
+        Ok(())
+    }
+
+    /// Whether the stream is soft-deleted, given the version of the last event ever appended
+    /// to it.
+    pub(crate) fn is_deleted(&self, last: Option<EventStreamVersion>) -> bool {
+        self.deleted && last.is_none_or(|last| last.value() < self.truncate_before)
+    }
+
+    /// The version expected-version checks see, given the version of the last event ever
+    /// appended to the stream. A soft-deleted stream doesn't exist until it is recreated.
+    pub(crate) fn current(&self, last: Option<EventStreamVersion>) -> Option<EventStreamVersion> {
+        last.filter(|_| !self.is_deleted(last))
+    }
+
+    /// Whether the stream's event at `version` is no longer read.
+    pub(crate) fn hides(&self, version: EventStreamVersion) -> bool {
+        self.tombstoned || version.value() < self.truncate_before
+    }
+
+    /// Soft-deletes the stream whose last event is at `last`. Once it is recreated, its
+    /// earlier events stay discarded.
+    pub(crate) fn delete(&mut self, last: EventStreamVersion) {
+        self.deleted = true;
+        self.truncate_before = last.value() + 1;
+    }
+
+    pub(crate) fn truncate(&mut self, before: EventStreamVersion) {
+        self.truncate_before = self.truncate_before.max(before.value());
+    }
+}
@@ -2,7 +2,7 @@ use eventstore::ClientSettingsParseError;
 use std::fmt::Debug;
 use thiserror::Error;
 
-use crate::event_store::{EventStreamId, EventStreamVersion};
+use crate::event_store::{EventStreamId, EventStreamVersion, ExpectedVersion, GlobalPosition};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -12,18 +12,27 @@ pub enum Error {
     #[error(transparent)]
     EventDeserializationError(#[from] serde_json::error::Error),
 
+    #[error("Failed to encode or decode {content_type} event payload")]
+    EventCodec {
+        content_type: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
     #[error("Stream not found: {stream_id}", stream_id = .0.to_string())]
     EventStoreStreamNotFound(EventStreamId),
 
-    #[error("Version mismatch for stream '{stream:?}': {:?}", match (&expected, &actual) {
-        (Some(e), Some(a)) => format!("expected version {:?}, but stream is at version {:?}", e, a),
-        (Some(e), None) => format!("expected version {:?}, but stream does not exist", e),
-        (None, Some(a)) => format!("stream exists at version {:?}, but no version was expected", a),
-        (None, None) => "invalid version state".to_string()
+    /// The stream was tombstoned, so it can no longer be read, appended to or deleted.
+    #[error("Stream deleted: {stream_id}", stream_id = .0.to_string())]
+    EventStoreStreamDeleted(EventStreamId),
+
+    #[error("Version mismatch for stream '{stream:?}': expected {expected}, but {}", match actual {
+        Some(a) => format!("stream is at version {}", a.value()),
+        None => "stream does not exist".to_string(),
     })]
     EventStoreVersionMismatch {
         stream: EventStreamId,
-        expected: Option<EventStreamVersion>,
+        expected: ExpectedVersion,
         actual: Option<EventStreamVersion>,
         #[source]
         source: eventstore::Error,
@@ -32,17 +41,40 @@ pub enum Error {
     #[error(transparent)]
     EventStoreOther(#[from] eventstore::Error),
 
-    #[error("Command failed (attempt {attempt} of {max_attempts}): {message}")]
-    CommandFailed {
-        message: String,
-        attempt: u32,
-        max_attempts: u32,
+    #[error(transparent)]
+    EventStoreDatabase(#[from] sqlx::Error),
+
+    #[error(transparent)]
+    EventStoreIo(#[from] std::io::Error),
+
+    #[error("Projection '{projection}' failed to handle the event at {position:?}")]
+    ProjectionFailed {
+        projection: String,
+        position: GlobalPosition,
         #[source]
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
-    #[error("Command execution exceeded maximum retries ({max_retries}) for stream '{stream}'")]
-    MaxRetriesExceeded { stream: String, max_retries: u32 },
+    /// An append reported a stream version lower than the number of events it appended.
+    #[error("Appending {appended} events to stream '{stream}' reported version {version:?}")]
+    UnexpectedAppendVersion {
+        stream: String,
+        appended: usize,
+        version: Option<EventStreamVersion>,
+    },
+
+    /// The retry policy gave up. `max_retries` is its limit, which a `RetryBudget` may have
+    /// cut short; `transient_retries` counts the retries that followed transient errors rather
+    /// than version mismatches.
+    #[error(
+        "Command execution exceeded maximum retries ({max_retries}) for stream '{stream}' \
+         ({transient_retries} retries after transient errors)"
+    )]
+    MaxRetriesExceeded {
+        stream: String,
+        max_retries: u32,
+        transient_retries: u32,
+    },
 
     #[error("Invalid configuration{}: {message}", parameter.as_ref().map(|p| format!(" parameter '{p}'")).unwrap_or_default())]
     InvalidConfig {
@@ -50,3 +82,87 @@ pub enum Error {
         parameter: Option<String>,
     },
 }
+
+/// Why executing a command failed, keeping the command's own error type so callers can match
+/// on domain rejections separately from failures to execute it at all.
+#[derive(Debug, Error)]
+pub enum ExecuteError<E> {
+    /// The command's handler rejected it.
+    #[error("Command rejected: {0}")]
+    Rejected(E),
+
+    #[error(transparent)]
+    Failed(#[from] Error),
+}
+
+/// gRPC status codes Kurrent reports for conditions that clear up on their own.
+const GRPC_DEADLINE_EXCEEDED: i32 = 4;
+const GRPC_RESOURCE_EXHAUSTED: i32 = 8;
+const GRPC_ABORTED: i32 = 10;
+const GRPC_UNAVAILABLE: i32 = 14;
+
+/// Database error codes for lock contention and serialization failures: SQLite's busy and
+/// locked results, and Postgres's serialization failure and deadlock.
+const TRANSIENT_DATABASE_CODES: [&str; 6] = ["5", "6", "261", "517", "40001", "40P01"];
+
+impl Error {
+    /// Whether the operation that failed is likely to succeed if tried again after a while, such
+    /// as when the server was unavailable, a deadline passed or the database was locked.
+    ///
+    /// Version mismatches are not transient: they need the command to be handled again against
+    /// the stream's new state.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::EventStoreOther(e) => match e {
+                eventstore::Error::ConnectionClosed
+                | eventstore::Error::DeadlineExceeded
+                | eventstore::Error::NotLeaderException(_)
+                | eventstore::Error::GrpcConnectionError(_) => true,
+                eventstore::Error::Grpc { code, .. } => matches!(
+                    *code as i32,
+                    GRPC_DEADLINE_EXCEEDED
+                        | GRPC_RESOURCE_EXHAUSTED
+                        | GRPC_ABORTED
+                        | GRPC_UNAVAILABLE
+                ),
+                _ => false,
+            },
+            Error::EventStoreDatabase(e) => match e {
+                sqlx::Error::PoolTimedOut | sqlx::Error::Io(_) => true,
+                sqlx::Error::Database(e) => e
+                    .code()
+                    .is_some_and(|code| TRANSIENT_DATABASE_CODES.contains(&code.as_ref())),
+                _ => false,
+            },
+            Error::EventStoreIo(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::WouldBlock
+            ),
+            _ => false,
+        }
+    }
+
+    /// Builds a version mismatch for adapters that check expected versions themselves, carrying
+    /// the same source error Kurrent would have reported.
+    pub(crate) fn version_mismatch(
+        stream: EventStreamId,
+        expected: ExpectedVersion,
+        actual: Option<EventStreamVersion>,
+    ) -> Self {
+        let source = eventstore::Error::WrongExpectedVersion {
+            expected: expected.into(),
+            current: match actual {
+                Some(v) => eventstore::CurrentRevision::Current(v.value()),
+                None => eventstore::CurrentRevision::NoStream,
+            },
+        };
+        Error::EventStoreVersionMismatch {
+            stream,
+            expected,
+            actual,
+            source,
+        }
+    }
+}
@@ -1,8 +1,22 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+use crate::codec::EventCodec;
+
 pub trait Event: Debug + for<'de> Deserialize<'de> + Serialize + Send + Sync + Sized {
     fn event_type(&self) -> String;
+
+    /// The version of the event's serialized shape, recorded with it. Bump it whenever the
+    /// shape changes and register an upcaster in `Upcasters` from the previous version.
+    fn schema_version(&self) -> u32 {
+        1
+    }
+
+    /// The codec to write this event with, overriding the store's codec. Events read back are
+    /// decoded with the codec registered in `Codecs` for the content type they were written with.
+    fn codec(&self) -> Option<&'static dyn EventCodec> {
+        None
+    }
 }
 
 impl Event for () {
@@ -10,4 +24,3 @@ impl Event for () {
         "None".to_string()
     }
 }
-
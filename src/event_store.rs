@@ -1,19 +1,166 @@
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{Error, Event, EventStream};
+use crate::{
+    Error, Event, EventStream, NewEvent, ReadAllOptions, ReadStreamOptions, RecordedEvent,
+    StreamMetadata, Subscription,
+};
 
 pub trait EventStore {
+    /// Appends events to a stream, recording each with its id and metadata. The append fails
+    /// with `Error::EventStoreVersionMismatch` unless the stream is in the state
+    /// `expected_version` describes.
+    fn append<E: Event>(
+        &mut self,
+        stream_id: EventStreamId,
+        events: Vec<NewEvent<E>>,
+        expected_version: ExpectedVersion,
+    ) -> impl std::future::Future<Output = Result<AppendResult, Error>> + Send;
+
+    /// Appends events with fresh ids and no metadata.
     fn publish<E: Event>(
         &mut self,
         stream_id: EventStreamId,
         events: Vec<E>,
-        expected_version: Option<EventStreamVersion>,
-    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+        expected_version: ExpectedVersion,
+    ) -> impl std::future::Future<Output = Result<AppendResult, Error>> + Send {
+        self.append(
+            stream_id,
+            events.into_iter().map(NewEvent::new).collect(),
+            expected_version,
+        )
+    }
+
+    /// Appends events with ids derived from `idempotency_key` and no metadata.
+    ///
+    /// Publishing the same events under the same key and expected version again, as a retry
+    /// after a timeout might, succeeds without writing them twice and returns the result of the
+    /// append that did.
+    fn publish_idempotent<E: Event>(
+        &mut self,
+        stream_id: EventStreamId,
+        events: Vec<E>,
+        expected_version: ExpectedVersion,
+        idempotency_key: &str,
+    ) -> impl std::future::Future<Output = Result<AppendResult, Error>> + Send {
+        self.append(
+            stream_id,
+            events
+                .into_iter()
+                .enumerate()
+                .map(|(index, event)| {
+                    NewEvent::new(event).with_idempotency_key(idempotency_key, index)
+                })
+                .collect(),
+            expected_version,
+        )
+    }
 
     fn read_stream<E: Event>(
         &self,
         stream_id: EventStreamId,
     ) -> impl std::future::Future<Output = Result<EventStream<E>, Error>> + Send;
+
+    /// Reads the events of a stream that follow version `after`.
+    fn read_stream_after<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        after: EventStreamVersion,
+    ) -> impl std::future::Future<Output = Result<EventStream<E>, Error>> + Send;
+
+    /// Reads the events of a stream `options` selects, in the direction it reads in.
+    fn read_stream_with<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        options: ReadStreamOptions,
+    ) -> impl std::future::Future<Output = Result<EventStream<E>, Error>> + Send;
+
+    /// Reads a stream's last event, or `None` if all of its events were truncated away.
+    fn read_last<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+    ) -> impl std::future::Future<Output = Result<Option<RecordedEvent<E>>, Error>> + Send {
+        let read =
+            self.read_stream_with(stream_id, ReadStreamOptions::new().backwards().max_count(1));
+        async move { read.await?.next_recorded().await }
+    }
+
+    /// Subscribes to a stream, starting after `from` or at the beginning of the stream if `from`
+    /// is `None`. The stream need not exist yet.
+    fn subscribe_to_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        from: Option<EventStreamVersion>,
+    ) -> impl std::future::Future<Output = Result<Subscription<E>, Error>> + Send;
+
+    /// Reads events from every stream in the order they were committed.
+    ///
+    /// Every event returned is deserialized as `E`, so stores holding events of several types
+    /// should be read with a filter.
+    fn read_all<E: Event>(
+        &self,
+        options: ReadAllOptions,
+    ) -> impl std::future::Future<Output = Result<EventStream<E>, Error>> + Send;
+
+    /// Subscribes to events from every stream in the order they were committed, catching up
+    /// from the position in `options` before following new events.
+    fn subscribe_to_all<E: Event>(
+        &self,
+        options: ReadAllOptions,
+    ) -> impl std::future::Future<Output = Result<Subscription<E>, Error>> + Send;
+
+    /// Returns the position of the most recently committed event, or `None` if the store is
+    /// empty.
+    fn head_position(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Option<GlobalPosition>, Error>> + Send;
+
+    /// Soft-deletes a stream, provided it is in the state `expected_version` describes. Reads
+    /// then find no stream and appends expecting `NoStream` succeed, recreating it with versions
+    /// that continue after the deleted events, which are never read again.
+    fn delete_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+
+    /// Permanently deletes a stream, provided it is in the state `expected_version` describes.
+    /// Reading, appending to or deleting the stream afterwards fails with
+    /// `Error::EventStoreStreamDeleted`.
+    fn tombstone_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+
+    /// Discards the events of a stream before version `before`, so reads start at `before`.
+    /// The stream keeps its version, so commands replaying it should restore their state from a
+    /// snapshot taken at or after `before`.
+    fn truncate_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        before: EventStreamVersion,
+    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+
+    /// Reads a stream's metadata and the version it was last written at, or empty metadata and
+    /// `None` if it was never written. Passing the version back to `set_stream_metadata` makes
+    /// the write fail if the metadata changed in between.
+    fn read_stream_metadata(
+        &self,
+        stream_id: EventStreamId,
+    ) -> impl std::future::Future<
+        Output = Result<(StreamMetadata, Option<EventStreamVersion>), Error>,
+    > + Send;
+
+    /// Replaces a stream's metadata, provided it was last written at the version
+    /// `expected_version` describes, and returns the version it is now at. The stream itself
+    /// doesn't have to exist.
+    fn set_stream_metadata(
+        &mut self,
+        stream_id: EventStreamId,
+        metadata: StreamMetadata,
+        expected_version: ExpectedVersion,
+    ) -> impl std::future::Future<Output = Result<EventStreamVersion, Error>> + Send;
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -41,7 +188,7 @@ impl std::fmt::Display for EventStreamId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct EventStreamVersion(u64);
 
 impl EventStreamVersion {
@@ -53,3 +200,148 @@ impl EventStreamVersion {
         self.0
     }
 }
+
+/// The state a stream must be in for an append to it to succeed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedVersion {
+    /// The stream must not exist yet.
+    NoStream,
+    /// The stream's last event must be at this version.
+    Exact(EventStreamVersion),
+    /// The stream may be in any state.
+    Any,
+    /// The stream must already exist, at any version.
+    StreamExists,
+}
+
+impl ExpectedVersion {
+    /// Whether a stream whose last event is at `current` is in the expected state.
+    pub fn matches(&self, current: Option<EventStreamVersion>) -> bool {
+        match self {
+            ExpectedVersion::NoStream => current.is_none(),
+            ExpectedVersion::Exact(version) => current == Some(*version),
+            ExpectedVersion::Any => true,
+            ExpectedVersion::StreamExists => current.is_some(),
+        }
+    }
+}
+
+/// Expects the stream to be at `version`, or not to exist if it is `None`.
+impl From<Option<EventStreamVersion>> for ExpectedVersion {
+    fn from(version: Option<EventStreamVersion>) -> Self {
+        version.map_or(ExpectedVersion::NoStream, ExpectedVersion::Exact)
+    }
+}
+
+impl std::fmt::Display for ExpectedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpectedVersion::NoStream => write!(f, "no stream"),
+            ExpectedVersion::Exact(version) => write!(f, "version {}", version.value()),
+            ExpectedVersion::Any => write!(f, "any version"),
+            ExpectedVersion::StreamExists => write!(f, "an existing stream"),
+        }
+    }
+}
+
+impl From<ExpectedVersion> for eventstore::ExpectedRevision {
+    fn from(expected: ExpectedVersion) -> Self {
+        match expected {
+            ExpectedVersion::NoStream => eventstore::ExpectedRevision::NoStream,
+            ExpectedVersion::Exact(version) => eventstore::ExpectedRevision::Exact(version.value()),
+            ExpectedVersion::Any => eventstore::ExpectedRevision::Any,
+            ExpectedVersion::StreamExists => eventstore::ExpectedRevision::StreamExists,
+        }
+    }
+}
+
+impl From<eventstore::ExpectedRevision> for ExpectedVersion {
+    fn from(expected: eventstore::ExpectedRevision) -> Self {
+        match expected {
+            eventstore::ExpectedRevision::NoStream => ExpectedVersion::NoStream,
+            eventstore::ExpectedRevision::Exact(version) => {
+                ExpectedVersion::Exact(EventStreamVersion::new(version))
+            }
+            eventstore::ExpectedRevision::Any => ExpectedVersion::Any,
+            eventstore::ExpectedRevision::StreamExists => ExpectedVersion::StreamExists,
+        }
+    }
+}
+
+/// Where the events of a successful append were written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendResult {
+    version: Option<EventStreamVersion>,
+    position: Option<GlobalPosition>,
+}
+
+impl AppendResult {
+    pub fn new(version: Option<EventStreamVersion>, position: Option<GlobalPosition>) -> Self {
+        Self { version, position }
+    }
+
+    /// The version of the last event appended, or `None` if there were no events to append.
+    pub fn version(&self) -> Option<EventStreamVersion> {
+        self.version
+    }
+
+    /// The global position of the last event appended, or `None` if there were no events to
+    /// append.
+    pub fn position(&self) -> Option<GlobalPosition> {
+        self.position
+    }
+}
+
+/// Emulates Kurrent's idempotent-write check for the other adapters.
+///
+/// `recorded` holds the id, version and position of the stream's events from the one with the
+/// same id as the first of the events being appended on. If `ids` are exactly the ids of those
+/// events, and `expected_version` would have allowed appending them where they were, this is a
+/// replay of an append that already happened and its result is returned.
+pub(crate) fn replayed_append(
+    ids: impl IntoIterator<Item = Uuid, IntoIter: ExactSizeIterator>,
+    recorded: &[(Uuid, EventStreamVersion, GlobalPosition)],
+    expected_version: ExpectedVersion,
+) -> Option<AppendResult> {
+    let ids = ids.into_iter();
+    let &(_, first_version, _) = recorded.first()?;
+    let before = first_version
+        .value()
+        .checked_sub(1)
+        .map(EventStreamVersion::new);
+    let &(_, version, position) = recorded.get(ids.len().checked_sub(1)?)?;
+
+    let replayed = expected_version.matches(before)
+        && ids
+            .zip(recorded)
+            .all(|(event_id, &(id, _, _))| event_id == id);
+    replayed.then(|| AppendResult::new(Some(version), Some(position)))
+}
+
+/// A position in the global log of every event in a store, in commit order.
+///
+/// Kurrent reports separate commit and prepare positions; the other adapters number events
+/// with a single sequence and use it for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct GlobalPosition {
+    commit: u64,
+    prepare: u64,
+}
+
+impl GlobalPosition {
+    pub fn new(commit: u64, prepare: u64) -> Self {
+        Self { commit, prepare }
+    }
+
+    pub(crate) fn from_sequence(sequence: u64) -> Self {
+        Self::new(sequence, sequence)
+    }
+
+    pub fn commit(&self) -> u64 {
+        self.commit
+    }
+
+    pub fn prepare(&self) -> u64 {
+        self.prepare
+    }
+}
@@ -0,0 +1,382 @@
+use chrono::{DateTime, Utc};
+
+use crate::codec::{Codecs, EventCodec};
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_store::{EventStreamId, EventStreamVersion, GlobalPosition};
+use crate::kurrent_adapter::{kurrent_position, stream_error};
+use crate::metadata::EventMetadata;
+use crate::new_event::NewEvent;
+use crate::read_all::ReadAllOptions;
+use crate::recorded_event::RecordedEvent;
+use crate::upcast::Upcasters;
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+pub struct EventStream<E: Event> {
+    source: EventSource,
+    codecs: Codecs,
+    upcasters: Upcasters,
+    read_through: Option<GlobalPosition>,
+    type_marker: PhantomData<E>,
+}
+
+enum EventSource {
+    Kurrent {
+        stream: Box<eventstore::ReadStream>,
+        /// The stream being read, or `None` for reads of `$all`.
+        stream_id: Option<EventStreamId>,
+        all: Option<ReadAllOptions>,
+        pages: Option<Box<KurrentPages>>,
+    },
+    Stored {
+        events: std::vec::IntoIter<StoredEvent>,
+        /// How far through the global log a read of it went once `events` run out.
+        end: Option<GlobalPosition>,
+    },
+}
+
+/// The number of events a filtered read of `$all` fetches from Kurrent at a time, whether or not
+/// they match.
+const FILTERED_PAGE_SIZE: u64 = 1024;
+
+/// The pages a read of `$all` with a `max_count` or a filter is fetched from Kurrent in. The
+/// server's limit also counts the events skipped as they arrive, so once a page runs out before
+/// `max_count` is reached, the next one is read from where it ended, past any events the filter
+/// skipped.
+pub(crate) struct KurrentPages {
+    client: eventstore::Client,
+    page_size: usize,
+    received: usize,
+    last: Option<GlobalPosition>,
+}
+
+impl KurrentPages {
+    /// Pages a read with `options`, or `None` if it reads every event to the end of `$all`.
+    pub(crate) fn new(client: eventstore::Client, options: &ReadAllOptions) -> Option<Self> {
+        Some(Self {
+            client,
+            page_size: page_size(page_count(options)?, options.after.is_some()),
+            received: 0,
+            last: None,
+        })
+    }
+
+    pub(crate) fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Reads the next page, unless the last one ended the log or `options` needs no more events.
+    async fn next_page(
+        &mut self,
+        options: &mut ReadAllOptions,
+    ) -> Result<Option<eventstore::ReadStream>, Error> {
+        let (Some(last), Some(count)) = (self.last, page_count(options)) else {
+            return Ok(None);
+        };
+        if self.received < self.page_size || options.max_count == Some(0) {
+            return Ok(None);
+        }
+        // The next page starts with the last event received, which is skipped again.
+        options.after = Some(last);
+        self.page_size = page_size(count, true);
+        self.received = 0;
+        let read_options = eventstore::ReadAllOptions::default()
+            .position(eventstore::StreamPosition::Position(kurrent_position(last)))
+            .max_count(self.page_size);
+        Ok(Some(self.client.read_all(&read_options).await?))
+    }
+}
+
+/// How many events the next page fetches: a filtered read's fixed page, or else the events an
+/// unfiltered read still needs, if it is limited at all.
+fn page_count(options: &ReadAllOptions) -> Option<u64> {
+    match &options.filter {
+        Some(_) => Some(FILTERED_PAGE_SIZE),
+        None => options.max_count,
+    }
+}
+
+/// The server's limit for `count` events, plus the event at the starting position, which reads
+/// of `$all` include.
+fn page_size(count: u64, after: bool) -> usize {
+    usize::try_from(count)
+        .unwrap_or(usize::MAX)
+        .saturating_add(usize::from(after))
+}
+
+/// How far through the global log a read with `options` that returned `events` went, given the
+/// log's `head` when it was read. A read cut short by its `max_count` went only as far as its
+/// last event; any other read went through to the head.
+pub(crate) fn read_through(
+    events: &[StoredEvent],
+    options: &ReadAllOptions,
+    head: Option<GlobalPosition>,
+) -> Option<GlobalPosition> {
+    let full = options
+        .max_count
+        .is_some_and(|count| events.len() as u64 >= count);
+    if full {
+        return events.last().map(|last| last.position).or(options.after);
+    }
+    head.max(options.after)
+}
+
+/// An event as persisted by the in-process adapters, prior to deserialization.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredEvent {
+    pub(crate) id: Uuid,
+    pub(crate) stream_id: EventStreamId,
+    pub(crate) version: EventStreamVersion,
+    pub(crate) position: GlobalPosition,
+    pub(crate) event_type: String,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) metadata: EventMetadata,
+    pub(crate) data: Vec<u8>,
+}
+
+impl StoredEvent {
+    pub(crate) fn encode<E: Event>(
+        stream_id: EventStreamId,
+        version: EventStreamVersion,
+        position: GlobalPosition,
+        created: DateTime<Utc>,
+        event: &NewEvent<E>,
+        codec: &dyn EventCodec,
+    ) -> Result<Self, Error> {
+        let (data, metadata) = event.encode(codec)?;
+        Ok(Self {
+            id: event.id(),
+            stream_id,
+            version,
+            position,
+            event_type: event.event().event_type(),
+            created,
+            metadata,
+            data,
+        })
+    }
+
+    pub(crate) fn decode<E: Event>(
+        &self,
+        codecs: &Codecs,
+        upcasters: &Upcasters,
+    ) -> Result<RecordedEvent<E>, Error> {
+        let event = upcasters.decode(codecs, &self.event_type, &self.metadata, &self.data)?;
+        Ok(RecordedEvent {
+            event,
+            id: self.id,
+            event_type: self.event_type.clone(),
+            stream_id: self.stream_id.clone(),
+            version: self.version,
+            position: self.position,
+            created: self.created,
+            metadata: self.metadata.clone(),
+        })
+    }
+}
+
+/// Returns the stream an event read from Kurrent belongs to, or `None` if it was not written
+/// through mneme, such as system events and events in streams not named by an `EventStreamId`.
+pub(crate) fn kurrent_stream_id(original: &eventstore::RecordedEvent) -> Option<EventStreamId> {
+    if original.event_type.starts_with('$') {
+        return None;
+    }
+    Uuid::parse_str(&original.stream_id)
+        .ok()
+        .map(EventStreamId::from_uuid)
+}
+
+/// Decodes an event read from Kurrent, or returns `None` if it should be skipped as described
+/// for `kurrent_stream_id`.
+pub(crate) fn decode_resolved<E: Event>(
+    resolved: &eventstore::ResolvedEvent,
+    codecs: &Codecs,
+    upcasters: &Upcasters,
+) -> Result<Option<RecordedEvent<E>>, Error> {
+    let original = resolved.get_original_event();
+    let Some(stream_id) = kurrent_stream_id(original) else {
+        return Ok(None);
+    };
+    let metadata = if original.custom_metadata.is_empty() {
+        EventMetadata::default()
+    } else {
+        serde_json::from_slice(&original.custom_metadata)
+            .map_err(Error::EventDeserializationError)?
+    };
+    let event = upcasters.decode(codecs, &original.event_type, &metadata, &original.data)?;
+    Ok(Some(RecordedEvent {
+        event,
+        id: original.id,
+        event_type: original.event_type.clone(),
+        stream_id,
+        version: EventStreamVersion::new(original.revision),
+        position: GlobalPosition::new(original.position.commit, original.position.prepare),
+        created: original.created,
+        metadata,
+    }))
+}
+
+impl<E: Event> EventStream<E> {
+    pub(crate) fn from_kurrent(
+        stream: eventstore::ReadStream,
+        stream_id: EventStreamId,
+        codecs: Codecs,
+    ) -> Self {
+        Self {
+            source: EventSource::Kurrent {
+                stream: Box::new(stream),
+                stream_id: Some(stream_id),
+                all: None,
+                pages: None,
+            },
+            codecs,
+            upcasters: Upcasters::default(),
+            read_through: None,
+            type_marker: PhantomData,
+        }
+    }
+
+    /// Wraps a read of Kurrent's `$all` stream. Kurrent can't filter reads of `$all` on the
+    /// server, so the options are applied here as events arrive, and further `pages` are read
+    /// if the server's limit ran out first.
+    pub(crate) fn from_kurrent_all(
+        stream: eventstore::ReadStream,
+        options: ReadAllOptions,
+        pages: Option<KurrentPages>,
+        codecs: Codecs,
+    ) -> Self {
+        Self {
+            source: EventSource::Kurrent {
+                stream: Box::new(stream),
+                stream_id: None,
+                all: Some(options),
+                pages: pages.map(Box::new),
+            },
+            codecs,
+            upcasters: Upcasters::default(),
+            read_through: None,
+            type_marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn from_stored(events: Vec<StoredEvent>, codecs: Codecs) -> Self {
+        Self::from_stored_all(events, None, codecs)
+    }
+
+    /// Wraps the events a read of the global log returned, which went through the log as far
+    /// as `end`, past any events it skipped.
+    pub(crate) fn from_stored_all(
+        events: Vec<StoredEvent>,
+        end: Option<GlobalPosition>,
+        codecs: Codecs,
+    ) -> Self {
+        Self {
+            source: EventSource::Stored {
+                events: events.into_iter(),
+                end,
+            },
+            codecs,
+            upcasters: Upcasters::default(),
+            read_through: None,
+            type_marker: PhantomData,
+        }
+    }
+
+    /// Upcasts events recorded at older schema versions with `upcasters` before
+    /// deserializing them.
+    pub fn with_upcasters(mut self, upcasters: Upcasters) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    pub async fn next(&mut self) -> Result<Option<(E, EventStreamVersion)>, Error> {
+        Ok(self.next_recorded().await?.map(|recorded| {
+            let version = recorded.version();
+            (recorded.into_event(), version)
+        }))
+    }
+
+    /// The position of the last event in the global log a read of it has gone past, including
+    /// events its filter skipped. Reading on from there finds no event this read didn't return.
+    pub(crate) fn read_through(&self) -> Option<GlobalPosition> {
+        self.read_through
+    }
+
+    /// Returns the next event along with its stream and global position.
+    pub async fn next_recorded(&mut self) -> Result<Option<RecordedEvent<E>>, Error> {
+        match &mut self.source {
+            EventSource::Kurrent {
+                stream,
+                stream_id,
+                all,
+                pages,
+            } => loop {
+                let resolved = match stream.next().await {
+                    Ok(resolved) => resolved,
+                    Err(eventstore::Error::ResourceNotFound) => None,
+                    Err(source) => {
+                        return Err(match stream_id {
+                            Some(stream_id) => stream_error(stream_id.clone(), source),
+                            None => source.into(),
+                        });
+                    }
+                };
+                let Some(resolved) = resolved else {
+                    if let (Some(pages), Some(options)) = (pages.as_mut(), all.as_mut())
+                        && let Some(next) = pages.next_page(options).await?
+                    {
+                        **stream = next;
+                        continue;
+                    }
+                    return Ok(None);
+                };
+
+                if let Some(options) = all {
+                    let original = resolved.get_original_event();
+                    let position =
+                        GlobalPosition::new(original.position.commit, original.position.prepare);
+                    if let Some(pages) = pages {
+                        pages.received += 1;
+                        pages.last = Some(position);
+                    }
+                    // Reads of `$all` include the event at the starting position.
+                    if options.after.is_some_and(|after| position <= after) {
+                        continue;
+                    }
+                    let Some(stream_id) = kurrent_stream_id(original) else {
+                        self.read_through = Some(position);
+                        continue;
+                    };
+                    if let Some(filter) = &options.filter
+                        && !filter.matches(&stream_id, &original.event_type)
+                    {
+                        self.read_through = Some(position);
+                        continue;
+                    }
+                    match &mut options.max_count {
+                        Some(0) => return Ok(None),
+                        Some(remaining) => *remaining -= 1,
+                        None => {}
+                    }
+                    self.read_through = Some(position);
+                }
+
+                if let Some(recorded) = decode_resolved(&resolved, &self.codecs, &self.upcasters)? {
+                    return Ok(Some(recorded));
+                }
+            },
+            EventSource::Stored { events, end } => match events.next() {
+                None => {
+                    self.read_through = end.or(self.read_through);
+                    Ok(None)
+                }
+                Some(stored) => {
+                    let recorded = stored.decode(&self.codecs, &self.upcasters)?;
+                    self.read_through = Some(stored.position);
+                    Ok(Some(recorded))
+                }
+            },
+        }
+    }
+}
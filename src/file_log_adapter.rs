@@ -0,0 +1,1064 @@
+mod config;
+mod segment;
+
+pub use config::{FileLogConfig, FsyncPolicy};
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use tokio::sync::watch;
+
+use crate::codec::{Codecs, EventCodec};
+use crate::deletion::Deletion;
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_store::{
+    AppendResult, EventStore, EventStreamId, EventStreamVersion, ExpectedVersion, GlobalPosition,
+    replayed_append,
+};
+use crate::event_stream::{EventStream, StoredEvent, read_through};
+use crate::new_event::NewEvent;
+use crate::read_all::ReadAllOptions;
+use crate::read_stream::{ReadDirection, ReadStreamOptions, StreamPosition};
+use crate::stream_metadata::StreamMetadata;
+use crate::subscription::{CATCH_UP_BATCH_SIZE, CatchUpSource, Subscription};
+use segment::Frame;
+
+/// An `EventStore` that appends events to segment files in a local directory.
+///
+/// Each append is written as a single checksummed frame, so it is either entirely durable or
+/// entirely lost. On open, every segment is scanned to rebuild the stream index; a frame left
+/// incomplete at the end of the last segment by a crash is truncated away.
+///
+/// Segments are never rewritten, so deleting or truncating a stream records the deletion in a
+/// separate file and stops its events from being read, but leaves them on disk. Stream metadata
+/// is kept in a file of its own.
+///
+/// The log assumes it is the only writer to its directory. Clones share the same log.
+#[derive(Clone)]
+pub struct FileLog {
+    log: Arc<Mutex<Log>>,
+    changes: Arc<watch::Sender<()>>,
+    codec: Arc<dyn EventCodec>,
+    codecs: Codecs,
+}
+
+struct Log {
+    dir: PathBuf,
+    config: FileLogConfig,
+    active: File,
+    active_segment: u64,
+    active_len: u64,
+    unsynced_appends: u32,
+    next_position: u64,
+    /// Every frame in the log, in the order they were appended.
+    frames: Vec<FrameLocation>,
+    streams: HashMap<EventStreamId, StreamIndex>,
+    deletions: HashMap<EventStreamId, Deletion>,
+    /// Each stream's metadata, without its truncation, and the version it was last written at.
+    metadata: HashMap<EventStreamId, (StreamMetadata, EventStreamVersion)>,
+}
+
+/// The file deletions are recorded in, replaced as a whole each time one changes.
+const DELETIONS_FILE: &str = "deletions.json";
+
+/// The file stream metadata is kept in, replaced as a whole each time it changes.
+const METADATA_FILE: &str = "metadata.json";
+
+struct StreamIndex {
+    version: EventStreamVersion,
+    frames: Vec<FrameLocation>,
+    /// The ids of the stream's events, so that appends that replay none are told apart
+    /// without reading its frames.
+    ids: HashSet<uuid::Uuid>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FrameLocation {
+    segment: u64,
+    offset: u64,
+    len: u32,
+    last_version: EventStreamVersion,
+    last_position: u64,
+}
+
+impl FileLog {
+    /// Opens the log in `dir`, creating the directory if needed and recovering from any append
+    /// that was interrupted by a crash.
+    pub fn open(dir: impl AsRef<Path>, config: FileLogConfig) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut segments = segment::list_segments(&dir)?;
+        if segments.is_empty() {
+            segments.push(0);
+        }
+        let last_segment = *segments.last().expect("at least one segment");
+
+        let mut streams: HashMap<EventStreamId, StreamIndex> = HashMap::new();
+        let mut frames = Vec::new();
+        let mut active_len = 0;
+
+        for &segment in &segments {
+            let path = segment::segment_path(&dir, segment);
+            let bytes = match std::fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e.into()),
+            };
+            let scan = segment::scan(&bytes);
+
+            if scan.torn {
+                if segment != last_segment {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "corrupt frame at offset {} in segment {}",
+                            scan.valid_len,
+                            path.display()
+                        ),
+                    )
+                    .into());
+                }
+                let file = OpenOptions::new().write(true).open(&path)?;
+                file.set_len(scan.valid_len)?;
+                file.sync_all()?;
+            }
+
+            for scanned in scan.frames {
+                let Some(last) = scanned.frame.events.last() else {
+                    continue;
+                };
+                let location = FrameLocation {
+                    segment,
+                    offset: scanned.offset,
+                    len: scanned.len,
+                    last_version: last.version,
+                    last_position: last.position.commit(),
+                };
+                let last_version = last.version;
+                let index = streams
+                    .entry(scanned.frame.stream_id)
+                    .or_insert_with(|| StreamIndex {
+                        version: last_version,
+                        frames: Vec::new(),
+                        ids: HashSet::new(),
+                    });
+                index.version = last_version;
+                index.frames.push(location);
+                index
+                    .ids
+                    .extend(scanned.frame.events.iter().map(|event| event.id));
+                frames.push(location);
+            }
+
+            active_len = scan.valid_len;
+        }
+
+        let active = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment::segment_path(&dir, last_segment))?;
+
+        let deletions: HashMap<uuid::Uuid, Deletion> = read_map(&dir.join(DELETIONS_FILE))?;
+        let metadata: HashMap<uuid::Uuid, (StreamMetadata, u64)> =
+            read_map(&dir.join(METADATA_FILE))?;
+
+        Ok(Self {
+            codec: config.shared_codec(),
+            codecs: config.codecs().clone(),
+            log: Arc::new(Mutex::new(Log {
+                dir,
+                config,
+                active,
+                active_segment: last_segment,
+                active_len,
+                unsynced_appends: 0,
+                next_position: frames
+                    .last()
+                    .map_or(0, |f: &FrameLocation| f.last_position + 1),
+                frames,
+                streams,
+                deletions: deletions
+                    .into_iter()
+                    .map(|(id, deletion)| (EventStreamId::from_uuid(id), deletion))
+                    .collect(),
+                metadata: metadata
+                    .into_iter()
+                    .map(|(id, (metadata, version))| {
+                        (
+                            EventStreamId::from_uuid(id),
+                            (metadata, EventStreamVersion::new(version)),
+                        )
+                    })
+                    .collect(),
+            })),
+            changes: Arc::new(watch::Sender::new(())),
+        })
+    }
+
+    /// Forces every acknowledged append to stable storage, regardless of the fsync policy.
+    pub fn sync(&self) -> Result<(), Error> {
+        let mut log = self.log.lock().expect("file log lock poisoned");
+        log.active.sync_data()?;
+        log.unsynced_appends = 0;
+        Ok(())
+    }
+
+    /// Runs `f` with the log locked on a thread where blocking is allowed, as writes wait for
+    /// files to reach the disk.
+    async fn with_log<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Log) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let log = self.log.clone();
+        tokio::task::spawn_blocking(move || f(&mut log.lock().expect("file log lock poisoned")))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    /// Runs the read `f` on a thread where blocking is allowed, as reads lock the log and then
+    /// read frames from disk.
+    async fn read_log<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Mutex<Log>) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let log = self.log.clone();
+        tokio::task::spawn_blocking(move || f(&log))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    async fn events_after(
+        &self,
+        stream_id: EventStreamId,
+        after: Option<EventStreamVersion>,
+    ) -> Result<Option<Vec<StoredEvent>>, Error> {
+        let start = EventStreamVersion::new(after.map_or(0, |v| v.value() + 1));
+        self.read_events(
+            stream_id,
+            ReadStreamOptions::new().position(StreamPosition::Version(start)),
+        )
+        .await
+    }
+
+    /// The events of the stream `options` selects, reading only the frames that hold them, or
+    /// `None` if there is no such stream.
+    async fn read_events(
+        &self,
+        stream_id: EventStreamId,
+        options: ReadStreamOptions,
+    ) -> Result<Option<Vec<StoredEvent>>, Error> {
+        self.read_log(move |log| {
+            let (dir, frames, first, retention) = {
+                let log = log.lock().expect("file log lock poisoned");
+                let deletion = log.deletion(&stream_id);
+                deletion.ensure_not_tombstoned(&stream_id)?;
+                let index = match log.streams.get(&stream_id) {
+                    Some(index) if !deletion.is_deleted(Some(index.version)) => index,
+                    _ => return Ok(None),
+                };
+                let retention = log
+                    .metadata
+                    .get(&stream_id)
+                    .map(|(metadata, _)| metadata.retention(Some(index.version)))
+                    .unwrap_or_default();
+                (
+                    log.dir.clone(),
+                    index.frames.clone(),
+                    retention.first_version.max(deletion.truncate_before),
+                    retention,
+                )
+            };
+
+            // Frames are never modified once acknowledged, so they can be read without the lock.
+            let start = options.start_version();
+            let limit = options.limit();
+            let mut events = Vec::new();
+            let read = |location: &FrameLocation| {
+                let path = segment::segment_path(&dir, location.segment);
+                segment::read_frame(&path, location.offset, location.len)
+            };
+            match options.direction {
+                ReadDirection::Forwards => {
+                    let from = first.max(start);
+                    for location in frames.iter().filter(|l| l.last_version.value() >= from) {
+                        if events.len() >= limit {
+                            break;
+                        }
+                        events.extend(
+                            read(location)?
+                                .events
+                                .into_iter()
+                                .filter(|event| event.version.value() >= from)
+                                .filter(|event| retention.keeps(event)),
+                        );
+                    }
+                }
+                ReadDirection::Backwards => {
+                    for (i, location) in frames.iter().enumerate().rev() {
+                        if events.len() >= limit || location.last_version.value() < first {
+                            break;
+                        }
+                        // A stream's frames hold consecutive versions, so this one starts after the
+                        // one before it ends.
+                        if i > 0 && frames[i - 1].last_version.value() >= start {
+                            continue;
+                        }
+                        events.extend(
+                            read(location)?
+                                .events
+                                .into_iter()
+                                .rev()
+                                .filter(|event| (first..=start).contains(&event.version.value()))
+                                .filter(|event| retention.keeps(event)),
+                        );
+                    }
+                }
+            }
+            events.truncate(limit);
+            Ok(Some(events))
+        })
+        .await
+    }
+
+    /// The events a read of the global log with `options` returns, and the position it read
+    /// through, skipped events included.
+    async fn all_events(
+        &self,
+        options: ReadAllOptions,
+    ) -> Result<(Vec<StoredEvent>, Option<GlobalPosition>), Error> {
+        self.read_log(move |log| {
+            let after = options.after.map(|p| p.commit());
+            let (dir, frames, deletions, head) = {
+                let log = log.lock().expect("file log lock poisoned");
+                let start = log.frames.partition_point(|location| {
+                    after.is_some_and(|after| location.last_position <= after)
+                });
+                (
+                    log.dir.clone(),
+                    log.frames[start..].to_vec(),
+                    log.deletions.clone(),
+                    log.frames
+                        .last()
+                        .map(|location| GlobalPosition::from_sequence(location.last_position)),
+                )
+            };
+
+            let limit = options.max_count.map_or(usize::MAX, |count| count as usize);
+            let mut events = Vec::new();
+            for location in frames {
+                if events.len() >= limit {
+                    break;
+                }
+                let path = segment::segment_path(&dir, location.segment);
+                let frame = segment::read_frame(&path, location.offset, location.len)?;
+                events.extend(
+                    frame
+                        .events
+                        .into_iter()
+                        .filter(|event| after.is_none_or(|after| event.position.commit() > after))
+                        .filter(|event| {
+                            deletions
+                                .get(&event.stream_id)
+                                .is_none_or(|deletion| !deletion.hides(event.version))
+                        })
+                        .filter(|event| {
+                            options.filter.as_ref().is_none_or(|filter| {
+                                filter.matches(&event.stream_id, &event.event_type)
+                            })
+                        }),
+                );
+            }
+            events.truncate(limit);
+            let end = read_through(&events, &options, head);
+            Ok((events, end))
+        })
+        .await
+    }
+}
+
+impl Log {
+    fn deletion(&self, stream_id: &EventStreamId) -> Deletion {
+        self.deletions.get(stream_id).copied().unwrap_or_default()
+    }
+
+    /// Records `deletion` for the stream.
+    fn set_deletion(&mut self, stream_id: EventStreamId, deletion: Deletion) -> io::Result<()> {
+        let mut deletions = self.deletions.clone();
+        deletions.insert(stream_id, deletion);
+
+        let by_id: HashMap<_, _> = deletions.iter().map(|(id, d)| (id.0, *d)).collect();
+        self.replace_file(DELETIONS_FILE, &serde_json::to_vec(&by_id)?)?;
+        self.deletions = deletions;
+        Ok(())
+    }
+
+    /// Records the stream's metadata, without its truncation, and the version it is now at.
+    fn set_metadata(
+        &mut self,
+        stream_id: EventStreamId,
+        metadata: StreamMetadata,
+        version: EventStreamVersion,
+    ) -> io::Result<()> {
+        let mut all = self.metadata.clone();
+        all.insert(stream_id, (metadata, version));
+
+        let by_id: HashMap<_, _> = all
+            .iter()
+            .map(|(id, (metadata, version))| (id.0, (metadata, version.value())))
+            .collect();
+        self.replace_file(METADATA_FILE, &serde_json::to_vec(&by_id)?)?;
+        self.metadata = all;
+        Ok(())
+    }
+
+    /// Replaces the file `name` in the log's directory with `contents`, so that it holds either
+    /// its old contents or the new ones after a crash.
+    fn replace_file(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let temp_path = self.dir.join(format!("{name}.tmp"));
+        let mut temp = File::create(&temp_path)?;
+        temp.write_all(contents)?;
+        temp.sync_all()?;
+        std::fs::rename(&temp_path, self.dir.join(name))?;
+        File::open(&self.dir)?.sync_all()
+    }
+
+    /// The result of the earlier append `events` replay, if they were already appended.
+    fn replayed_append(
+        &self,
+        stream_id: &EventStreamId,
+        events: &[StoredEvent],
+        expected_version: ExpectedVersion,
+    ) -> Result<Option<AppendResult>, Error> {
+        let (Some(first), Some(index)) = (events.first(), self.streams.get(stream_id)) else {
+            return Ok(None);
+        };
+        if !index.ids.contains(&first.id) {
+            return Ok(None);
+        }
+
+        let mut recorded = Vec::new();
+        for location in &index.frames {
+            let path = segment::segment_path(&self.dir, location.segment);
+            let frame = segment::read_frame(&path, location.offset, location.len)?;
+            let found = !recorded.is_empty();
+            recorded.extend(
+                frame
+                    .events
+                    .into_iter()
+                    .skip_while(|event| !found && event.id != first.id)
+                    .map(|event| (event.id, event.version, event.position)),
+            );
+            if recorded.len() >= events.len() {
+                break;
+            }
+        }
+        let ids = events.iter().map(|event| event.id);
+        Ok(replayed_append(ids, &recorded, expected_version))
+    }
+
+    /// Appends `events`, numbering them from the stream's and the log's next version and
+    /// position.
+    fn append_events(
+        &mut self,
+        stream_id: EventStreamId,
+        mut events: Vec<StoredEvent>,
+        expected_version: ExpectedVersion,
+    ) -> Result<AppendResult, Error> {
+        let deletion = self.deletion(&stream_id);
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let last = self.streams.get(&stream_id).map(|index| index.version);
+        let current = deletion.current(last);
+
+        if (expected_version == ExpectedVersion::Any || !expected_version.matches(current))
+            && let Some(result) = self.replayed_append(&stream_id, &events, expected_version)?
+        {
+            return Ok(result);
+        }
+
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        // A recreated stream continues from the version it was deleted at.
+        let next_version = last.map_or(0, |v| v.value() + 1);
+        for ((event, version), position) in events
+            .iter_mut()
+            .zip(next_version..)
+            .zip(self.next_position..)
+        {
+            event.version = EventStreamVersion::new(version);
+            event.position = GlobalPosition::from_sequence(position);
+        }
+
+        let Some((last_version, last_position)) =
+            events.last().map(|event| (event.version, event.position))
+        else {
+            return Ok(AppendResult::new(None, None));
+        };
+
+        let ids: Vec<_> = events.iter().map(|event| event.id).collect();
+        let location = self.append(&Frame {
+            stream_id: stream_id.clone(),
+            events,
+        })?;
+
+        let index = self
+            .streams
+            .entry(stream_id)
+            .or_insert_with(|| StreamIndex {
+                version: last_version,
+                frames: Vec::new(),
+                ids: HashSet::new(),
+            });
+        index.version = last_version;
+        index.frames.push(location);
+        index.ids.extend(ids);
+        Ok(AppendResult::new(Some(last_version), Some(last_position)))
+    }
+
+    fn append(&mut self, frame: &Frame) -> io::Result<FrameLocation> {
+        let bytes = frame.encode();
+        let last = frame.events.last().expect("frames hold at least one event");
+        let (last_version, last_position) = (last.version, last.position.commit());
+
+        if self.active_len > 0 && self.active_len + bytes.len() as u64 > self.config.segment_size()
+        {
+            self.roll_segment()?;
+        }
+
+        let unsynced_appends = self.unsynced_appends + 1;
+        let sync_now = match self.config.fsync_policy() {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Batched { appends } => unsynced_appends >= appends,
+            FsyncPolicy::Os => false,
+        };
+        let written = self.active.write_all(&bytes).and_then(|()| {
+            if sync_now {
+                self.active.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = written {
+            // Don't leave the frame behind: the append failed, so a retry writes the same
+            // versions again, after it.
+            self.active.set_len(self.active_len)?;
+            return Err(e);
+        }
+        self.unsynced_appends = if sync_now { 0 } else { unsynced_appends };
+
+        let location = FrameLocation {
+            segment: self.active_segment,
+            offset: self.active_len,
+            len: (bytes.len() - segment::HEADER_LEN) as u32,
+            last_version,
+            last_position,
+        };
+        self.active_len += bytes.len() as u64;
+        self.next_position = last_position + 1;
+        self.frames.push(location);
+
+        Ok(location)
+    }
+
+    fn roll_segment(&mut self) -> io::Result<()> {
+        if self.config.fsync_policy() != FsyncPolicy::Os {
+            self.active.sync_data()?;
+            self.unsynced_appends = 0;
+        }
+
+        let next_segment = self.active_segment + 1;
+        self.active = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment::segment_path(&self.dir, next_segment))?;
+        self.active_segment = next_segment;
+        self.active_len = 0;
+
+        if self.config.fsync_policy() != FsyncPolicy::Os {
+            // Make the new segment's directory entry durable along with its contents.
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl EventStore for FileLog {
+    async fn append<E: Event>(
+        &mut self,
+        stream_id: EventStreamId,
+        events: Vec<NewEvent<E>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<AppendResult, Error> {
+        // Stored to the microsecond, so truncate now to read back what was written.
+        let created = DateTime::from_timestamp_micros(Utc::now().timestamp_micros())
+            .expect("current time is in range");
+        // Encoded here, as the events can't be moved to the thread that writes them. Their
+        // versions and positions are only known once the log is locked.
+        let stored = events
+            .iter()
+            .map(|event| {
+                StoredEvent::encode(
+                    stream_id.clone(),
+                    EventStreamVersion::new(0),
+                    GlobalPosition::from_sequence(0),
+                    created,
+                    event,
+                    self.codec.as_ref(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let result = self
+            .with_log(move |log| log.append_events(stream_id, stored, expected_version))
+            .await?;
+        self.changes.send_replace(());
+        Ok(result)
+    }
+
+    async fn read_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+    ) -> Result<EventStream<E>, Error> {
+        match self.events_after(stream_id.clone(), None).await? {
+            Some(events) => Ok(EventStream::from_stored(events, self.codecs.clone())),
+            None => Err(Error::EventStoreStreamNotFound(stream_id)),
+        }
+    }
+
+    async fn read_stream_after<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        after: EventStreamVersion,
+    ) -> Result<EventStream<E>, Error> {
+        match self.events_after(stream_id.clone(), Some(after)).await? {
+            Some(events) => Ok(EventStream::from_stored(events, self.codecs.clone())),
+            None => Err(Error::EventStoreStreamNotFound(stream_id)),
+        }
+    }
+
+    async fn read_stream_with<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        options: ReadStreamOptions,
+    ) -> Result<EventStream<E>, Error> {
+        match self.read_events(stream_id.clone(), options).await? {
+            Some(events) => Ok(EventStream::from_stored(events, self.codecs.clone())),
+            None => Err(Error::EventStoreStreamNotFound(stream_id)),
+        }
+    }
+
+    async fn subscribe_to_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        from: Option<EventStreamVersion>,
+    ) -> Result<Subscription<E>, Error> {
+        Ok(Subscription::from_catch_up(
+            StreamCatchUp {
+                store: self.clone(),
+                changes: self.changes.subscribe(),
+                stream_id,
+                after: from,
+            },
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn read_all<E: Event>(&self, options: ReadAllOptions) -> Result<EventStream<E>, Error> {
+        let (events, end) = self.all_events(options).await?;
+        Ok(EventStream::from_stored_all(
+            events,
+            end,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn subscribe_to_all<E: Event>(
+        &self,
+        options: ReadAllOptions,
+    ) -> Result<Subscription<E>, Error> {
+        Ok(Subscription::from_catch_up(
+            AllCatchUp {
+                store: self.clone(),
+                changes: self.changes.subscribe(),
+                options: options.max_count(CATCH_UP_BATCH_SIZE),
+            },
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn head_position(&self) -> Result<Option<GlobalPosition>, Error> {
+        self.read_log(|log| {
+            Ok(log
+                .lock()
+                .expect("file log lock poisoned")
+                .frames
+                .last()
+                .map(|location| GlobalPosition::from_sequence(location.last_position)))
+        })
+        .await
+    }
+
+    async fn delete_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        self.with_log(move |log| {
+            let mut deletion = log.deletion(&stream_id);
+            deletion.ensure_not_tombstoned(&stream_id)?;
+            let current = deletion.current(log.streams.get(&stream_id).map(|index| index.version));
+            if !expected_version.matches(current) {
+                return Err(Error::version_mismatch(
+                    stream_id,
+                    expected_version,
+                    current,
+                ));
+            }
+
+            if let Some(last) = current {
+                deletion.delete(last);
+                log.set_deletion(stream_id, deletion)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn tombstone_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        self.with_log(move |log| {
+            let mut deletion = log.deletion(&stream_id);
+            deletion.ensure_not_tombstoned(&stream_id)?;
+            let current = deletion.current(log.streams.get(&stream_id).map(|index| index.version));
+            if !expected_version.matches(current) {
+                return Err(Error::version_mismatch(
+                    stream_id,
+                    expected_version,
+                    current,
+                ));
+            }
+
+            deletion.tombstoned = true;
+            log.set_deletion(stream_id, deletion)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn truncate_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        before: EventStreamVersion,
+    ) -> Result<(), Error> {
+        self.with_log(move |log| {
+            let mut deletion = log.deletion(&stream_id);
+            deletion.ensure_not_tombstoned(&stream_id)?;
+            deletion.truncate(before);
+            log.set_deletion(stream_id, deletion)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn read_stream_metadata(
+        &self,
+        stream_id: EventStreamId,
+    ) -> Result<(StreamMetadata, Option<EventStreamVersion>), Error> {
+        self.read_log(move |log| {
+            let log = log.lock().expect("file log lock poisoned");
+            let deletion = log.deletion(&stream_id);
+            deletion.ensure_not_tombstoned(&stream_id)?;
+            let (metadata, version) = match log.metadata.get(&stream_id) {
+                Some((metadata, version)) => (metadata.clone(), Some(*version)),
+                None => (StreamMetadata::default(), None),
+            };
+            Ok((metadata.with_truncation(&deletion), version))
+        })
+        .await
+    }
+
+    async fn set_stream_metadata(
+        &mut self,
+        stream_id: EventStreamId,
+        metadata: StreamMetadata,
+        expected_version: ExpectedVersion,
+    ) -> Result<EventStreamVersion, Error> {
+        self.with_log(move |log| {
+            let mut deletion = log.deletion(&stream_id);
+            deletion.ensure_not_tombstoned(&stream_id)?;
+            let current = log.metadata.get(&stream_id).map(|&(_, version)| version);
+            if !expected_version.matches(current) {
+                return Err(Error::version_mismatch(
+                    stream_id,
+                    expected_version,
+                    current,
+                ));
+            }
+
+            let (metadata, truncate_before) = metadata.split_truncation();
+            if let Some(before) = truncate_before {
+                deletion.truncate(before);
+                log.set_deletion(stream_id.clone(), deletion)?;
+            }
+            let version = current.map_or(EventStreamVersion::new(0), |v| {
+                EventStreamVersion::new(v.value() + 1)
+            });
+            log.set_metadata(stream_id, metadata, version)?;
+            Ok(version)
+        })
+        .await
+    }
+}
+
+/// Reads a map of per-stream records, which is empty until the first is written.
+fn read_map<T: serde::de::DeserializeOwned>(path: &Path) -> Result<HashMap<uuid::Uuid, T>, Error> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+struct StreamCatchUp {
+    store: FileLog,
+    changes: watch::Receiver<()>,
+    stream_id: EventStreamId,
+    after: Option<EventStreamVersion>,
+}
+
+impl CatchUpSource for StreamCatchUp {
+    fn fetch(&mut self) -> BoxFuture<'_, Result<Vec<StoredEvent>, Error>> {
+        // Mark the current state as seen before reading, so an append that lands after the
+        // read still wakes the next `wait`.
+        self.changes.borrow_and_update();
+        Box::pin(async move {
+            let events = self
+                .store
+                .events_after(self.stream_id.clone(), self.after)
+                .await?
+                .unwrap_or_default();
+            if let Some(last) = events.last() {
+                self.after = Some(last.version);
+            }
+            Ok(events)
+        })
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let _ = self.changes.changed().await;
+        })
+    }
+}
+
+struct AllCatchUp {
+    store: FileLog,
+    changes: watch::Receiver<()>,
+    options: ReadAllOptions,
+}
+
+impl CatchUpSource for AllCatchUp {
+    fn fetch(&mut self) -> BoxFuture<'_, Result<Vec<StoredEvent>, Error>> {
+        self.changes.borrow_and_update();
+        Box::pin(async move {
+            let (events, end) = self.store.all_events(self.options.clone()).await?;
+            self.options.after = end.or(self.options.after);
+            Ok(events)
+        })
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let _ = self.changes.changed().await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_event::TestEvent;
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("mneme-{}", uuid::Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    async fn read_values(store: &FileLog, stream_id: EventStreamId) -> Vec<u32> {
+        let mut stream = store.read_stream(stream_id).await.unwrap();
+        let mut values = vec![];
+        while let Some((TestEvent::Happened { value }, _)) = stream.next().await.unwrap() {
+            values.push(value);
+        }
+        values
+    }
+
+    async fn publish_values(store: &mut FileLog, stream_id: &EventStreamId, values: &[u32]) {
+        let events = values
+            .iter()
+            .map(|&value| TestEvent::Happened { value })
+            .collect();
+        store
+            .publish(stream_id.clone(), events, ExpectedVersion::Any)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn events_persist_across_reopen() {
+        let dir = TestDir::new();
+        let stream_id = EventStreamId::new();
+
+        let mut store = FileLog::open(&dir.0, FileLogConfig::default()).unwrap();
+        publish_values(&mut store, &stream_id, &[1, 2]).await;
+        drop(store);
+
+        let mut store = FileLog::open(&dir.0, FileLogConfig::default()).unwrap();
+        assert_eq!(read_values(&store, stream_id.clone()).await, vec![1, 2]);
+
+        match store
+            .publish(
+                stream_id.clone(),
+                vec![TestEvent::Happened { value: 3 }],
+                ExpectedVersion::Exact(EventStreamVersion::new(0)),
+            )
+            .await
+        {
+            Err(Error::EventStoreVersionMismatch {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, ExpectedVersion::Exact(EventStreamVersion::new(0)));
+                assert_eq!(actual, Some(EventStreamVersion::new(1)));
+            }
+            other => panic!("Expected version mismatch error, got: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn rolls_over_to_new_segments() {
+        let dir = TestDir::new();
+        let config = FileLogConfig::default()
+            .with_segment_size(64)
+            .unwrap()
+            .with_fsync_policy(FsyncPolicy::Os)
+            .unwrap();
+        let first = EventStreamId::new();
+        let second = EventStreamId::new();
+
+        let mut store = FileLog::open(&dir.0, config.clone()).unwrap();
+        for value in 0..5 {
+            publish_values(&mut store, &first, &[value]).await;
+            publish_values(&mut store, &second, &[value + 10]).await;
+        }
+        assert!(segment::list_segments(&dir.0).unwrap().len() > 1);
+        drop(store);
+
+        let store = FileLog::open(&dir.0, config).unwrap();
+        assert_eq!(read_values(&store, first).await, vec![0, 1, 2, 3, 4]);
+        assert_eq!(read_values(&store, second).await, vec![10, 11, 12, 13, 14]);
+    }
+
+    #[tokio::test]
+    async fn truncates_torn_tail_on_open() {
+        let dir = TestDir::new();
+        let stream_id = EventStreamId::new();
+
+        let mut store = FileLog::open(&dir.0, FileLogConfig::default()).unwrap();
+        publish_values(&mut store, &stream_id, &[1]).await;
+        drop(store);
+
+        // Simulate a crash part way through writing a second frame.
+        let path = segment::segment_path(&dir.0, 0);
+        let intact_len = std::fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let mut store = FileLog::open(&dir.0, FileLogConfig::default()).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), intact_len);
+
+        publish_values(&mut store, &stream_id, &[2]).await;
+        drop(store);
+
+        let store = FileLog::open(&dir.0, FileLogConfig::default()).unwrap();
+        assert_eq!(read_values(&store, stream_id).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn rejects_corruption_before_last_segment() {
+        let dir = TestDir::new();
+        let config = FileLogConfig::default().with_segment_size(16).unwrap();
+        let stream_id = EventStreamId::new();
+
+        let mut store = FileLog::open(&dir.0, config.clone()).unwrap();
+        publish_values(&mut store, &stream_id, &[1]).await;
+        publish_values(&mut store, &stream_id, &[2]).await;
+        drop(store);
+
+        let path = segment::segment_path(&dir.0, 0);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        match FileLog::open(&dir.0, config) {
+            Err(Error::EventStoreIo(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            Err(other) => panic!("Expected io error, got: {:?}", other),
+            Ok(_) => panic!("Expected io error, got an open log"),
+        }
+    }
+
+    #[tokio::test]
+    async fn global_positions_continue_across_reopen() {
+        let dir = TestDir::new();
+        let first = EventStreamId::new();
+        let second = EventStreamId::new();
+
+        let mut store = FileLog::open(&dir.0, FileLogConfig::default()).unwrap();
+        publish_values(&mut store, &first, &[1, 2]).await;
+        drop(store);
+
+        let mut store = FileLog::open(&dir.0, FileLogConfig::default()).unwrap();
+        publish_values(&mut store, &second, &[3]).await;
+
+        let mut stream = store
+            .read_all::<TestEvent>(ReadAllOptions::new().after(GlobalPosition::from_sequence(0)))
+            .await
+            .unwrap();
+        let mut recorded = vec![];
+        while let Some(event) = stream.next_recorded().await.unwrap() {
+            recorded.push((event.stream_id().clone(), event.position()));
+        }
+        assert_eq!(
+            recorded,
+            vec![
+                (first, GlobalPosition::from_sequence(1)),
+                (second, GlobalPosition::from_sequence(2)),
+            ]
+        );
+    }
+}
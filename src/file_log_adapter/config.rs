@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use crate::codec::{Codecs, EventCodec, Json};
+use crate::error::Error;
+
+const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// When appended data is forced to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Sync before every append is acknowledged. Nothing acknowledged is ever lost.
+    Always,
+    /// Sync once `appends` appends have accumulated since the last sync. Up to that many
+    /// acknowledged appends may be lost on power failure.
+    Batched { appends: u32 },
+    /// Never sync explicitly; the operating system flushes in its own time.
+    Os,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileLogConfig {
+    fsync_policy: FsyncPolicy,
+    segment_size: u64,
+    codec: Arc<dyn EventCodec>,
+    codecs: Codecs,
+}
+
+impl FileLogConfig {
+    pub fn with_fsync_policy(mut self, fsync_policy: FsyncPolicy) -> Result<Self, Error> {
+        if let FsyncPolicy::Batched { appends: 0 } = fsync_policy {
+            return Err(Error::InvalidConfig {
+                message: "batched fsync appends cannot be 0".to_string(),
+                parameter: Some("fsync_policy".to_string()),
+            });
+        }
+        self.fsync_policy = fsync_policy;
+        Ok(self)
+    }
+
+    /// Sets the size in bytes after which a new segment file is started. A single append is
+    /// never split across segments, so a segment may exceed this by up to one append.
+    pub fn with_segment_size(mut self, segment_size: u64) -> Result<Self, Error> {
+        if segment_size == 0 {
+            return Err(Error::InvalidConfig {
+                message: "segment_size cannot be 0".to_string(),
+                parameter: Some("segment_size".to_string()),
+            });
+        }
+        self.segment_size = segment_size;
+        Ok(self)
+    }
+
+    /// Sets the codec events are serialized with, unless their type names its own, and registers
+    /// it to read them back with.
+    pub fn with_codec(mut self, codec: impl EventCodec + 'static) -> Self {
+        self.codec = Arc::new(codec);
+        self.codecs = self.codecs.register_shared(self.codec.clone());
+        self
+    }
+
+    /// Sets the codecs events are read back with, chosen by the content type each was written
+    /// with. The codec the log writes with is always among them.
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs.register_shared(self.codec.clone());
+        self
+    }
+
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        self.fsync_policy
+    }
+
+    pub fn segment_size(&self) -> u64 {
+        self.segment_size
+    }
+
+    pub fn codec(&self) -> &dyn EventCodec {
+        self.codec.as_ref()
+    }
+
+    pub fn codecs(&self) -> &Codecs {
+        &self.codecs
+    }
+
+    pub(crate) fn shared_codec(&self) -> Arc<dyn EventCodec> {
+        self.codec.clone()
+    }
+}
+
+impl Default for FileLogConfig {
+    fn default() -> Self {
+        Self {
+            fsync_policy: FsyncPolicy::Always,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            codec: Arc::new(Json),
+            codecs: Codecs::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_fsync_policy() {
+        match FileLogConfig::default().with_fsync_policy(FsyncPolicy::Batched { appends: 0 }) {
+            Err(Error::InvalidConfig { message, parameter }) => {
+                assert_eq!(message, "batched fsync appends cannot be 0");
+                assert_eq!(parameter, Some("fsync_policy".to_string()));
+            }
+            other => panic!("Expected InvalidConfig error, got {:?}", other),
+        }
+
+        let config = FileLogConfig::default()
+            .with_fsync_policy(FsyncPolicy::Batched { appends: 10 })
+            .expect("Failed to set valid fsync policy");
+        assert_eq!(config.fsync_policy(), FsyncPolicy::Batched { appends: 10 });
+    }
+
+    #[test]
+    fn validates_segment_size() {
+        match FileLogConfig::default().with_segment_size(0) {
+            Err(Error::InvalidConfig { message, parameter }) => {
+                assert_eq!(message, "segment_size cannot be 0");
+                assert_eq!(parameter, Some("segment_size".to_string()));
+            }
+            other => panic!("Expected InvalidConfig error, got {:?}", other),
+        }
+
+        let config = FileLogConfig::default()
+            .with_segment_size(1024)
+            .expect("Failed to set valid segment size");
+        assert_eq!(config.segment_size(), 1024);
+    }
+}
@@ -0,0 +1,303 @@
+//! On-disk layout of a segment file.
+//!
+//! A segment is a sequence of frames, one per append:
+//!
+//! | length: u32 LE | crc32: u32 LE | payload: `length` bytes |
+//!
+//! The payload holds the stream id and the global position of the append's first event,
+//! followed by every event of the append with its id, creation time and metadata, so an append
+//! is either entirely present or entirely absent. The checksum covers the payload and lets
+//! recovery tell a complete frame from one that was cut short by a crash.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use chrono::DateTime;
+use uuid::Uuid;
+
+use crate::event_store::{EventStreamId, EventStreamVersion, GlobalPosition};
+use crate::event_stream::StoredEvent;
+
+pub(crate) const HEADER_LEN: usize = 8;
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+pub(crate) struct Frame {
+    pub(crate) stream_id: EventStreamId,
+    pub(crate) events: Vec<StoredEvent>,
+}
+
+/// A frame found while scanning a segment, along with where it lives.
+pub(crate) struct ScannedFrame {
+    pub(crate) offset: u64,
+    pub(crate) len: u32,
+    pub(crate) frame: Frame,
+}
+
+pub(crate) struct Scan {
+    pub(crate) frames: Vec<ScannedFrame>,
+    /// Length of the segment up to the end of the last intact frame.
+    pub(crate) valid_len: u64,
+    /// Whether anything follows the last intact frame.
+    pub(crate) torn: bool,
+}
+
+impl Frame {
+    /// Encodes the frame, header included.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let first_position = self
+            .events
+            .first()
+            .map_or(0, |event| event.position.commit());
+        payload.extend_from_slice(self.stream_id.0.as_bytes());
+        payload.extend_from_slice(&first_position.to_le_bytes());
+        payload.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        for event in &self.events {
+            let metadata =
+                serde_json::to_vec(&event.metadata).expect("event metadata serializes to JSON");
+            payload.extend_from_slice(event.id.as_bytes());
+            payload.extend_from_slice(&event.version.value().to_le_bytes());
+            payload.extend_from_slice(&event.created.timestamp_micros().to_le_bytes());
+            payload.extend_from_slice(&(event.event_type.len() as u32).to_le_bytes());
+            payload.extend_from_slice(event.event_type.as_bytes());
+            payload.extend_from_slice(&(event.data.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&event.data);
+            payload.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&metadata);
+        }
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    fn decode(payload: &[u8]) -> io::Result<Self> {
+        let mut reader = PayloadReader { bytes: payload };
+        let stream_id = EventStreamId(Uuid::from_bytes(reader.take()?));
+        let first_position = u64::from_le_bytes(reader.take()?);
+        let count = u32::from_le_bytes(reader.take()?);
+        let events = (first_position..first_position + count as u64)
+            .map(|position| {
+                let id = Uuid::from_bytes(reader.take()?);
+                let version = EventStreamVersion::new(u64::from_le_bytes(reader.take()?));
+                let created = DateTime::from_timestamp_micros(i64::from_le_bytes(reader.take()?))
+                    .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "event timestamp out of range")
+                })?;
+                let len = u32::from_le_bytes(reader.take()?) as usize;
+                let event_type = String::from_utf8(reader.take_slice(len)?.to_vec())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let len = u32::from_le_bytes(reader.take()?) as usize;
+                let data = reader.take_slice(len)?.to_vec();
+                let len = u32::from_le_bytes(reader.take()?) as usize;
+                let metadata = serde_json::from_slice(reader.take_slice(len)?)?;
+                Ok(StoredEvent {
+                    id,
+                    stream_id: stream_id.clone(),
+                    version,
+                    position: GlobalPosition::from_sequence(position),
+                    event_type,
+                    created,
+                    metadata,
+                    data,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { stream_id, events })
+    }
+}
+
+/// Reads every intact frame of a segment, stopping at the first one that is incomplete or
+/// fails its checksum.
+pub(crate) fn scan(bytes: &[u8]) -> Scan {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+
+    while let Some((len, payload)) = frame_at(bytes, offset) {
+        match Frame::decode(payload) {
+            Ok(frame) => frames.push(ScannedFrame {
+                offset: offset as u64,
+                len,
+                frame,
+            }),
+            Err(_) => break,
+        }
+        offset += HEADER_LEN + len as usize;
+    }
+
+    Scan {
+        frames,
+        valid_len: offset as u64,
+        torn: offset < bytes.len(),
+    }
+}
+
+/// Reads the frame of `len` payload bytes at `offset` in the segment at `path`.
+pub(crate) fn read_frame(path: &Path, offset: u64, len: u32) -> io::Result<Frame> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = vec![0; HEADER_LEN + len as usize];
+    file.read_exact(&mut bytes)?;
+
+    match frame_at(&bytes, 0) {
+        Some((_, payload)) => Frame::decode(payload),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "corrupt frame at offset {offset} in segment {}",
+                path.display()
+            ),
+        )),
+    }
+}
+
+pub(crate) fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{segment:020}.{SEGMENT_EXTENSION}"))
+}
+
+/// Lists the numbers of the segments in `dir`, in order.
+pub(crate) fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(segment) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            segments.push(segment);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Returns the payload length and payload of the frame at `offset`, if a complete frame with a
+/// matching checksum is there.
+fn frame_at(bytes: &[u8], offset: usize) -> Option<(u32, &[u8])> {
+    let header = bytes.get(offset..offset + HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[0..4].try_into().ok()?);
+    let checksum = u32::from_le_bytes(header[4..8].try_into().ok()?);
+    let start = offset + HEADER_LEN;
+    let payload = bytes.get(start..start + len as usize)?;
+    (crc32(payload) == checksum).then_some((len, payload))
+}
+
+struct PayloadReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let slice = self.take_slice(N)?;
+        Ok(slice.try_into().expect("slice has requested length"))
+    }
+
+    fn take_slice(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame payload is truncated",
+            ));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+}
+
+/// CRC-32 (IEEE), as used by zlib and gzip.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::EventMetadata;
+
+    fn frame(versions: &[u64]) -> Frame {
+        let stream_id = EventStreamId::new();
+        Frame {
+            stream_id: stream_id.clone(),
+            events: versions
+                .iter()
+                .map(|&v| StoredEvent {
+                    id: Uuid::new_v4(),
+                    stream_id: stream_id.clone(),
+                    version: EventStreamVersion::new(v),
+                    position: GlobalPosition::from_sequence(v + 100),
+                    event_type: "TestEvent.Happened".to_string(),
+                    created: DateTime::from_timestamp_micros(1_700_000_000_000_000).unwrap(),
+                    metadata: EventMetadata::new().with_correlation_id(Uuid::new_v4()),
+                    data: format!("{{\"v\":{v}}}").into_bytes(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn computes_standard_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn scans_intact_frames() {
+        let mut bytes = frame(&[0, 1]).encode();
+        bytes.extend(frame(&[0]).encode());
+
+        let scan = scan(&bytes);
+        assert_eq!(scan.frames.len(), 2);
+        let events = &scan.frames[0].frame.events;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].position, GlobalPosition::from_sequence(101));
+        assert_eq!(events[1].event_type, "TestEvent.Happened");
+        assert_eq!(events[1].created, frame(&[0]).events[0].created);
+        assert!(events[1].metadata.correlation_id().is_some());
+        assert_eq!(scan.valid_len, bytes.len() as u64);
+        assert!(!scan.torn);
+    }
+
+    #[test]
+    fn stops_at_truncated_frame() {
+        let first = frame(&[0]).encode();
+        let second = frame(&[1]).encode();
+        let mut bytes = first.clone();
+        bytes.extend_from_slice(&second[..second.len() - 3]);
+
+        let scan = scan(&bytes);
+        assert_eq!(scan.frames.len(), 1);
+        assert_eq!(scan.valid_len, first.len() as u64);
+        assert!(scan.torn);
+    }
+
+    #[test]
+    fn stops_at_checksum_mismatch() {
+        let first = frame(&[0]).encode();
+        let mut second = frame(&[1]).encode();
+        let last = second.len() - 1;
+        second[last] ^= 0xFF;
+        let mut bytes = first.clone();
+        bytes.extend(second);
+
+        let scan = scan(&bytes);
+        assert_eq!(scan.frames.len(), 1);
+        assert_eq!(scan.valid_len, first.len() as u64);
+        assert!(scan.torn);
+    }
+}
@@ -0,0 +1,644 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use tokio::sync::watch;
+
+use crate::codec::{Codecs, EventCodec, Json};
+use crate::deletion::Deletion;
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_store::{
+    AppendResult, EventStore, EventStreamId, EventStreamVersion, ExpectedVersion, GlobalPosition,
+    replayed_append,
+};
+use crate::event_stream::{EventStream, StoredEvent, read_through};
+use crate::new_event::NewEvent;
+use crate::read_all::ReadAllOptions;
+use crate::read_stream::{ReadDirection, ReadStreamOptions, StreamPosition};
+use crate::stream_metadata::StreamMetadata;
+use crate::subscription::{CATCH_UP_BATCH_SIZE, CatchUpSource, Subscription};
+
+/// An `EventStore` that keeps every stream in process memory.
+///
+/// Clones share the same underlying streams, so a clone can be handed to another task to
+/// simulate a concurrent writer. Nothing is persisted; this is intended for tests and for
+/// embedding in tools that do not need durability.
+#[derive(Clone)]
+pub struct InMemoryEventStore {
+    log: Arc<RwLock<Log>>,
+    changes: Arc<watch::Sender<()>>,
+    codec: Arc<dyn EventCodec>,
+    codecs: Codecs,
+}
+
+/// Every event in commit order, with each stream indexing into it. An event's global position
+/// is its index in `events`. Deleted and truncated events are kept but no longer read.
+#[derive(Default)]
+struct Log {
+    events: Vec<StoredEvent>,
+    streams: HashMap<EventStreamId, Vec<usize>>,
+    /// The ids of each stream's events, so that appends that replay none are told apart
+    /// without a scan.
+    ids: HashMap<EventStreamId, HashSet<uuid::Uuid>>,
+    deletions: HashMap<EventStreamId, Deletion>,
+    /// Each stream's metadata, without its truncation, and the version it was last written at.
+    metadata: HashMap<EventStreamId, (StreamMetadata, EventStreamVersion)>,
+}
+
+impl Log {
+    fn deletion(&self, stream_id: &EventStreamId) -> Deletion {
+        self.deletions.get(stream_id).copied().unwrap_or_default()
+    }
+
+    /// The version of the last event ever appended to the stream, deleted or not.
+    fn last_version(&self, stream_id: &EventStreamId) -> Option<EventStreamVersion> {
+        self.streams
+            .get(stream_id)
+            .and_then(|indices| indices.last())
+            .map(|&index| self.events[index].version)
+    }
+
+    /// The result of the earlier append `events` replay, if they were already appended.
+    fn replayed_append<E>(
+        &self,
+        stream_id: &EventStreamId,
+        events: &[NewEvent<E>],
+        expected_version: ExpectedVersion,
+    ) -> Option<AppendResult> {
+        let first_id = events.first()?.id();
+        if !self.ids.get(stream_id)?.contains(&first_id) {
+            return None;
+        }
+        let indices = self.streams.get(stream_id)?;
+        let start = indices
+            .iter()
+            .position(|&index| self.events[index].id == first_id)?;
+        let recorded: Vec<_> = indices[start..]
+            .iter()
+            .take(events.len())
+            .map(|&index| {
+                let event = &self.events[index];
+                (event.id, event.version, event.position)
+            })
+            .collect();
+        replayed_append(events.iter().map(NewEvent::id), &recorded, expected_version)
+    }
+}
+
+impl InMemoryEventStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the codec events are serialized with, unless their type names its own, and registers
+    /// it to read them back with.
+    pub fn with_codec(mut self, codec: impl EventCodec + 'static) -> Self {
+        self.codec = Arc::new(codec);
+        self.codecs = self.codecs.register_shared(self.codec.clone());
+        self
+    }
+
+    /// Sets the codecs events are read back with, chosen by the content type each was written
+    /// with. The codec the store writes with is always among them.
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs.register_shared(self.codec.clone());
+        self
+    }
+
+    fn events_after(
+        &self,
+        stream_id: &EventStreamId,
+        after: Option<EventStreamVersion>,
+    ) -> Result<Vec<StoredEvent>, Error> {
+        let start = EventStreamVersion::new(after.map_or(0, |v| v.value() + 1));
+        self.read_events(
+            stream_id,
+            &ReadStreamOptions::new().position(StreamPosition::Version(start)),
+        )
+    }
+
+    /// The events of the stream `options` selects, cloning only those.
+    fn read_events(
+        &self,
+        stream_id: &EventStreamId,
+        options: &ReadStreamOptions,
+    ) -> Result<Vec<StoredEvent>, Error> {
+        let log = self
+            .log
+            .read()
+            .expect("in-memory event store lock poisoned");
+
+        let deletion = log.deletion(stream_id);
+        deletion.ensure_not_tombstoned(stream_id)?;
+        let last = log.last_version(stream_id);
+        let indices = match log.streams.get(stream_id) {
+            Some(indices) if !deletion.is_deleted(last) => indices,
+            _ => return Err(Error::EventStoreStreamNotFound(stream_id.clone())),
+        };
+        let retention = log
+            .metadata
+            .get(stream_id)
+            .map(|(metadata, _)| metadata.retention(last))
+            .unwrap_or_default();
+
+        // A stream's versions are the offsets of its events in its index.
+        let len = indices.len() as u64;
+        let first = retention.first_version.max(deletion.truncate_before);
+        let start = options.start_version();
+        let selected: Box<dyn Iterator<Item = &usize>> = match options.direction {
+            ReadDirection::Forwards => {
+                Box::new(indices[first.max(start).min(len) as usize..].iter())
+            }
+            ReadDirection::Backwards => {
+                let end = start.saturating_add(1).min(len);
+                Box::new(indices[first.min(end) as usize..end as usize].iter().rev())
+            }
+        };
+        Ok(selected
+            .map(|&index| &log.events[index])
+            .filter(|event| retention.keeps(event))
+            .take(options.limit())
+            .cloned()
+            .collect())
+    }
+
+    /// The events a read of the global log with `options` returns, and the position it read
+    /// through, skipped events included.
+    fn all_events(&self, options: &ReadAllOptions) -> (Vec<StoredEvent>, Option<GlobalPosition>) {
+        let log = self
+            .log
+            .read()
+            .expect("in-memory event store lock poisoned");
+
+        let start = options.after.map_or(0, |p| p.commit() as usize + 1);
+        let events: Vec<_> = log
+            .events
+            .get(start..)
+            .unwrap_or_default()
+            .iter()
+            .filter(|event| !log.deletion(&event.stream_id).hides(event.version))
+            .filter(|event| {
+                options
+                    .filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(&event.stream_id, &event.event_type))
+            })
+            .take(options.max_count.map_or(usize::MAX, |count| count as usize))
+            .cloned()
+            .collect();
+        let head = log.events.last().map(|event| event.position);
+        let end = read_through(&events, options, head);
+        (events, end)
+    }
+}
+
+impl Default for InMemoryEventStore {
+    fn default() -> Self {
+        Self {
+            log: Default::default(),
+            changes: Arc::new(watch::Sender::new(())),
+            codec: Arc::new(Json),
+            codecs: Codecs::default(),
+        }
+    }
+}
+
+impl EventStore for InMemoryEventStore {
+    async fn append<E: Event>(
+        &mut self,
+        stream_id: EventStreamId,
+        events: Vec<NewEvent<E>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<AppendResult, Error> {
+        let mut log = self
+            .log
+            .write()
+            .expect("in-memory event store lock poisoned");
+
+        let deletion = log.deletion(&stream_id);
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let last = log.last_version(&stream_id);
+        let current = deletion.current(last);
+
+        if (expected_version == ExpectedVersion::Any || !expected_version.matches(current))
+            && let Some(result) = log.replayed_append(&stream_id, &events, expected_version)
+        {
+            return Ok(result);
+        }
+
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        // A recreated stream continues from the version it was deleted at.
+        let next_version = last.map_or(0, |v| v.value() + 1);
+        let next_index = log.events.len();
+        let created = Utc::now();
+        let stored = events
+            .iter()
+            .zip(next_version..)
+            .zip(next_index..)
+            .map(|((event, version), index)| {
+                StoredEvent::encode(
+                    stream_id.clone(),
+                    EventStreamVersion::new(version),
+                    GlobalPosition::from_sequence(index as u64),
+                    created,
+                    event,
+                    self.codec.as_ref(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let result = AppendResult::new(
+            stored.last().map(|event| event.version),
+            stored.last().map(|event| event.position),
+        );
+        if !stored.is_empty() {
+            let indices = next_index..next_index + stored.len();
+            let ids = stored.iter().map(|event| event.id);
+            log.ids.entry(stream_id.clone()).or_default().extend(ids);
+            log.events.extend(stored);
+            log.streams.entry(stream_id).or_default().extend(indices);
+            self.changes.send_replace(());
+        }
+        Ok(result)
+    }
+
+    async fn read_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+    ) -> Result<EventStream<E>, Error> {
+        Ok(EventStream::from_stored(
+            self.events_after(&stream_id, None)?,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn read_stream_after<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        after: EventStreamVersion,
+    ) -> Result<EventStream<E>, Error> {
+        Ok(EventStream::from_stored(
+            self.events_after(&stream_id, Some(after))?,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn read_stream_with<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        options: ReadStreamOptions,
+    ) -> Result<EventStream<E>, Error> {
+        Ok(EventStream::from_stored(
+            self.read_events(&stream_id, &options)?,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn subscribe_to_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        from: Option<EventStreamVersion>,
+    ) -> Result<Subscription<E>, Error> {
+        Ok(Subscription::from_catch_up(
+            StreamCatchUp {
+                store: self.clone(),
+                changes: self.changes.subscribe(),
+                stream_id,
+                after: from,
+            },
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn read_all<E: Event>(&self, options: ReadAllOptions) -> Result<EventStream<E>, Error> {
+        let (events, end) = self.all_events(&options);
+        Ok(EventStream::from_stored_all(
+            events,
+            end,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn subscribe_to_all<E: Event>(
+        &self,
+        options: ReadAllOptions,
+    ) -> Result<Subscription<E>, Error> {
+        Ok(Subscription::from_catch_up(
+            AllCatchUp {
+                store: self.clone(),
+                changes: self.changes.subscribe(),
+                options: options.max_count(CATCH_UP_BATCH_SIZE),
+            },
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn head_position(&self) -> Result<Option<GlobalPosition>, Error> {
+        let log = self
+            .log
+            .read()
+            .expect("in-memory event store lock poisoned");
+        Ok(log.events.last().map(|event| event.position))
+    }
+
+    async fn delete_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let mut log = self
+            .log
+            .write()
+            .expect("in-memory event store lock poisoned");
+
+        let mut deletion = log.deletion(&stream_id);
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let current = deletion.current(log.last_version(&stream_id));
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        if let Some(last) = current {
+            deletion.delete(last);
+            log.deletions.insert(stream_id, deletion);
+        }
+        Ok(())
+    }
+
+    async fn tombstone_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let mut log = self
+            .log
+            .write()
+            .expect("in-memory event store lock poisoned");
+
+        let mut deletion = log.deletion(&stream_id);
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let current = deletion.current(log.last_version(&stream_id));
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        deletion.tombstoned = true;
+        log.deletions.insert(stream_id, deletion);
+        Ok(())
+    }
+
+    async fn truncate_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        before: EventStreamVersion,
+    ) -> Result<(), Error> {
+        let mut log = self
+            .log
+            .write()
+            .expect("in-memory event store lock poisoned");
+
+        let mut deletion = log.deletion(&stream_id);
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        deletion.truncate(before);
+        log.deletions.insert(stream_id, deletion);
+        Ok(())
+    }
+
+    async fn read_stream_metadata(
+        &self,
+        stream_id: EventStreamId,
+    ) -> Result<(StreamMetadata, Option<EventStreamVersion>), Error> {
+        let log = self
+            .log
+            .read()
+            .expect("in-memory event store lock poisoned");
+
+        let deletion = log.deletion(&stream_id);
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let (metadata, version) = match log.metadata.get(&stream_id) {
+            Some((metadata, version)) => (metadata.clone(), Some(*version)),
+            None => (StreamMetadata::default(), None),
+        };
+        Ok((metadata.with_truncation(&deletion), version))
+    }
+
+    async fn set_stream_metadata(
+        &mut self,
+        stream_id: EventStreamId,
+        metadata: StreamMetadata,
+        expected_version: ExpectedVersion,
+    ) -> Result<EventStreamVersion, Error> {
+        let mut log = self
+            .log
+            .write()
+            .expect("in-memory event store lock poisoned");
+
+        let mut deletion = log.deletion(&stream_id);
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let current = log.metadata.get(&stream_id).map(|&(_, version)| version);
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        let (metadata, truncate_before) = metadata.split_truncation();
+        if let Some(before) = truncate_before {
+            deletion.truncate(before);
+            log.deletions.insert(stream_id.clone(), deletion);
+        }
+        let version = current.map_or(EventStreamVersion::new(0), |v| {
+            EventStreamVersion::new(v.value() + 1)
+        });
+        log.metadata.insert(stream_id, (metadata, version));
+        Ok(version)
+    }
+}
+
+struct StreamCatchUp {
+    store: InMemoryEventStore,
+    changes: watch::Receiver<()>,
+    stream_id: EventStreamId,
+    after: Option<EventStreamVersion>,
+}
+
+impl CatchUpSource for StreamCatchUp {
+    fn fetch(&mut self) -> BoxFuture<'_, Result<Vec<StoredEvent>, Error>> {
+        // Mark the current state as seen before reading, so an append that lands after the
+        // read still wakes the next `wait`.
+        self.changes.borrow_and_update();
+        let events = self
+            .store
+            .events_after(&self.stream_id, self.after)
+            .unwrap_or_default();
+        if let Some(last) = events.last() {
+            self.after = Some(last.version);
+        }
+        Box::pin(async move { Ok(events) })
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let _ = self.changes.changed().await;
+        })
+    }
+}
+
+struct AllCatchUp {
+    store: InMemoryEventStore,
+    changes: watch::Receiver<()>,
+    options: ReadAllOptions,
+}
+
+impl CatchUpSource for AllCatchUp {
+    fn fetch(&mut self) -> BoxFuture<'_, Result<Vec<StoredEvent>, Error>> {
+        self.changes.borrow_and_update();
+        let (events, end) = self.store.all_events(&self.options);
+        self.options.after = end.or(self.options.after);
+        Box::pin(async move { Ok(events) })
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let _ = self.changes.changed().await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::codec::Cbor;
+    use crate::read_all::EventFilter;
+    use crate::test_event::TestEvent;
+
+    #[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+    enum Other {
+        Noted,
+    }
+
+    impl Event for Other {
+        fn event_type(&self) -> String {
+            "Other.Noted".to_string()
+        }
+    }
+
+    async fn read_all(
+        store: &InMemoryEventStore,
+        stream_id: EventStreamId,
+    ) -> Vec<(TestEvent, EventStreamVersion)> {
+        let mut stream = store.read_stream(stream_id).await.unwrap();
+        let mut events = vec![];
+        while let Some(event) = stream.next().await.unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn writes_with_the_store_codec() {
+        let mut store = InMemoryEventStore::new().with_codec(Cbor);
+        let stream_id = EventStreamId::new();
+        store
+            .publish(
+                stream_id.clone(),
+                vec![TestEvent::Happened { value: 1 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+
+        let stored = store.events_after(&stream_id, None).unwrap();
+        assert_eq!(stored[0].metadata.content_type(), Some("application/cbor"));
+        assert_eq!(
+            read_all(&store, stream_id).await,
+            vec![(TestEvent::Happened { value: 1 }, EventStreamVersion::new(0))]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_all_filters_by_event_type_prefix() {
+        let mut store = InMemoryEventStore::new();
+        let first = EventStreamId::new();
+        let second = EventStreamId::new();
+
+        store
+            .publish(
+                first.clone(),
+                vec![TestEvent::Happened { value: 1 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+        store
+            .publish(second.clone(), vec![Other::Noted], ExpectedVersion::Any)
+            .await
+            .unwrap();
+        store
+            .publish(
+                first.clone(),
+                vec![TestEvent::Happened { value: 2 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+
+        let mut stream = store
+            .read_all::<TestEvent>(
+                ReadAllOptions::new().filter(EventFilter::event_type_prefix(["TestEvent."])),
+            )
+            .await
+            .unwrap();
+        let mut positions = vec![];
+        while let Some(recorded) = stream.next_recorded().await.unwrap() {
+            assert_eq!(recorded.stream_id(), &first);
+            positions.push(recorded.position());
+        }
+        assert_eq!(
+            positions,
+            vec![
+                GlobalPosition::from_sequence(0),
+                GlobalPosition::from_sequence(2)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn clones_share_streams() {
+        let store = InMemoryEventStore::new();
+        let mut writer = store.clone();
+        let stream_id = EventStreamId::new();
+
+        writer
+            .publish(
+                stream_id.clone(),
+                vec![TestEvent::Happened { value: 1 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(read_all(&store, stream_id).await.len(), 1);
+    }
+}
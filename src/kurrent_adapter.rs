@@ -2,22 +2,54 @@ mod settings;
 mod stream;
 
 pub use settings::ConnectionSettings;
-pub use stream::EventStream;
 
+use std::sync::Arc;
+
+use crate::codec::{self, Codecs, EventCodec, JSON_CONTENT_TYPE, Json};
 use crate::error::Error;
 use crate::event::Event;
-use crate::event_store::{EventStore, EventStreamId, EventStreamVersion};
+use crate::event_store::{
+    AppendResult, EventStore, EventStreamId, EventStreamVersion, ExpectedVersion, GlobalPosition,
+};
+use crate::event_stream::{EventStream, KurrentPages, kurrent_stream_id};
+use crate::metadata::EventMetadata;
+use crate::new_event::NewEvent;
+use crate::read_all::{EventFilter, ReadAllOptions};
+use crate::read_stream::{ReadDirection, ReadStreamOptions};
+use crate::stream_metadata::StreamMetadata;
+use crate::subscription::Subscription;
 use eventstore::AppendToStreamOptions;
 
 #[derive(Clone)]
 pub struct Kurrent {
     pub client: eventstore::Client,
+    codec: Arc<dyn EventCodec>,
+    codecs: Codecs,
 }
 
 impl Kurrent {
     pub fn new(settings: &ConnectionSettings) -> Result<Self, Error> {
         let client = eventstore::Client::new(settings.to_client_settings()?)?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            codec: Arc::new(Json),
+            codecs: Codecs::default(),
+        })
+    }
+
+    /// Sets the codec events are serialized with, unless their type names its own, and registers
+    /// it to read them back with.
+    pub fn with_codec(mut self, codec: impl EventCodec + 'static) -> Self {
+        self.codec = Arc::new(codec);
+        self.codecs = self.codecs.register_shared(self.codec.clone());
+        self
+    }
+
+    /// Sets the codecs events are read back with, chosen by the content type each was written
+    /// with. The codec the store writes with is always among them.
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs.register_shared(self.codec.clone());
+        self
     }
 
     pub fn from_env() -> Result<Self, Error> {
@@ -42,44 +74,37 @@ impl Kurrent {
         self.client
             .append_to_stream(stream_id.clone(), options, events)
             .await
-            .map_err(|source| match source {
-                eventstore::Error::ResourceNotFound => Error::EventStoreStreamNotFound(stream_id),
-                eventstore::Error::WrongExpectedVersion { current, expected } => {
-                    Error::EventStoreVersionMismatch {
-                        stream: stream_id,
-                        expected: extract_revision(&expected),
-                        actual: extract_current_revision(&current),
-                        source,
-                    }
-                }
-                e => Error::EventStoreOther(e),
-            })
+            .map_err(|source| stream_error(stream_id, source))
     }
 }
 
 impl EventStore for Kurrent {
-    async fn publish<E: Event>(
+    async fn append<E: Event>(
         &mut self,
         stream_id: EventStreamId,
-        events: Vec<E>,
-        expected_version: Option<EventStreamVersion>,
-    ) -> Result<(), Error> {
+        events: Vec<NewEvent<E>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<AppendResult, Error> {
         let events: Vec<eventstore::EventData> = events
             .iter()
-            .map(|event| {
-                let event_type = event.event_type();
-                eventstore::EventData::json(&event_type, &event)
-                    .map_err(Error::EventDeserializationError)
-            })
+            .map(|event| event_data(event, self.codec.as_ref()))
             .collect::<Result<_, _>>()?;
 
-        let options = AppendToStreamOptions::default().expected_revision(match expected_version {
-            Some(v) => eventstore::ExpectedRevision::Exact(v.value()),
-            None => eventstore::ExpectedRevision::Any,
-        });
+        let options = AppendToStreamOptions::default().expected_revision(expected_version.into());
 
-        self.append_to_stream(stream_id, &options, events).await?;
-        Ok(())
+        let appended = !events.is_empty();
+        let result = self.append_to_stream(stream_id, &options, events).await?;
+        Ok(if appended {
+            AppendResult::new(
+                Some(EventStreamVersion::new(result.next_expected_version)),
+                Some(GlobalPosition::new(
+                    result.position.commit,
+                    result.position.prepare,
+                )),
+            )
+        } else {
+            AppendResult::new(None, None)
+        })
     }
 
     async fn read_stream<E: Event>(
@@ -90,18 +115,216 @@ impl EventStore for Kurrent {
             .client
             .read_stream(stream_id.clone(), &Default::default())
             .await
-            .map(|stream| EventStream {
-                stream,
-                type_marker: std::marker::PhantomData,
-            })
-            .map_err(|source| match source {
-                eventstore::Error::ResourceNotFound => Error::EventStoreStreamNotFound(stream_id),
-                e => Error::EventStoreOther(e),
-            })?;
-        Ok(stream)
+            .map_err(|source| stream_error(stream_id.clone(), source))?;
+        Ok(EventStream::from_kurrent(
+            stream,
+            stream_id,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn read_stream_after<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        after: EventStreamVersion,
+    ) -> Result<EventStream<E>, Error> {
+        self.stream_builder(stream_id)
+            .position(eventstore::StreamPosition::Position(after.value() + 1))
+            .read()
+            .await
+    }
+
+    async fn read_stream_with<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        options: ReadStreamOptions,
+    ) -> Result<EventStream<E>, Error> {
+        let builder = self
+            .stream_builder(stream_id)
+            .direction(options.direction)
+            .position(options.start());
+        match options.max_count {
+            Some(count) => builder.max_count(count).read().await,
+            None => builder.read().await,
+        }
+    }
+
+    async fn subscribe_to_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        from: Option<EventStreamVersion>,
+    ) -> Result<Subscription<E>, Error> {
+        let options = eventstore::SubscribeToStreamOptions::default().start_from(match from {
+            Some(v) => eventstore::StreamPosition::Position(v.value()),
+            None => eventstore::StreamPosition::Start,
+        });
+        let subscription = self.client.subscribe_to_stream(stream_id, &options).await;
+        Ok(Subscription::from_kurrent(
+            subscription,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn read_all<E: Event>(&self, options: ReadAllOptions) -> Result<EventStream<E>, Error> {
+        let mut read_options =
+            eventstore::ReadAllOptions::default().position(match options.after {
+                Some(position) => eventstore::StreamPosition::Position(kurrent_position(position)),
+                None => eventstore::StreamPosition::Start,
+            });
+        let pages = KurrentPages::new(self.client.clone(), &options);
+        if let Some(pages) = &pages {
+            read_options = read_options.max_count(pages.page_size());
+        }
+        let stream = self.client.read_all(&read_options).await?;
+        Ok(EventStream::from_kurrent_all(
+            stream,
+            options,
+            pages,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn subscribe_to_all<E: Event>(
+        &self,
+        options: ReadAllOptions,
+    ) -> Result<Subscription<E>, Error> {
+        let mut subscribe_options =
+            eventstore::SubscribeToAllOptions::default().position(match options.after {
+                Some(position) => eventstore::StreamPosition::Position(kurrent_position(position)),
+                None => eventstore::StreamPosition::Start,
+            });
+        subscribe_options = subscribe_options.filter(match &options.filter {
+            Some(filter) => subscription_filter(filter),
+            None => eventstore::SubscriptionFilter::on_event_type().exclude_system_events(),
+        });
+        let subscription = self.client.subscribe_to_all(&subscribe_options).await;
+        Ok(Subscription::from_kurrent(
+            subscription,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn head_position(&self) -> Result<Option<GlobalPosition>, Error> {
+        let options = eventstore::ReadAllOptions::default()
+            .position(eventstore::StreamPosition::End)
+            .backwards();
+        let mut stream = self.client.read_all(&options).await?;
+        // Skip past system events, which mneme's reads of `$all` never return.
+        while let Some(resolved) = stream.next().await? {
+            let original = resolved.get_original_event();
+            if kurrent_stream_id(original).is_some() {
+                return Ok(Some(GlobalPosition::new(
+                    original.position.commit,
+                    original.position.prepare,
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn delete_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let options =
+            eventstore::DeleteStreamOptions::default().expected_revision(expected_version.into());
+        self.client
+            .delete_stream(stream_id.clone(), &options)
+            .await
+            .map_err(|source| stream_error(stream_id, source))?;
+        Ok(())
+    }
+
+    async fn tombstone_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let options = eventstore::TombstoneStreamOptions::default()
+            .expected_revision(expected_version.into());
+        self.client
+            .tombstone_stream(stream_id.clone(), &options)
+            .await
+            .map_err(|source| stream_error(stream_id, source))?;
+        Ok(())
+    }
+
+    /// Sets the stream's `$tb` metadata, keeping the rest of its metadata. Kurrent hides the
+    /// events before it at once and removes them on its next scavenge.
+    async fn truncate_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        before: EventStreamVersion,
+    ) -> Result<(), Error> {
+        let (metadata, version) = self.read_stream_metadata(stream_id.clone()).await?;
+        // Write against the revision just read, so metadata someone else sets meanwhile fails
+        // the truncation with a version mismatch instead of being overwritten.
+        let expected_version = ExpectedVersion::from(version);
+        self.set_stream_metadata(
+            stream_id,
+            metadata.with_truncate_before(before),
+            expected_version,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn read_stream_metadata(
+        &self,
+        stream_id: EventStreamId,
+    ) -> Result<(StreamMetadata, Option<EventStreamVersion>), Error> {
+        match self
+            .client
+            .get_stream_metadata(stream_id.to_string().as_str(), &Default::default())
+            .await
+            .map_err(|source| stream_error(stream_id.clone(), source))?
+        {
+            eventstore::StreamMetadataResult::Deleted => {
+                Err(Error::EventStoreStreamDeleted(stream_id))
+            }
+            eventstore::StreamMetadataResult::NotFound => Ok((StreamMetadata::default(), None)),
+            eventstore::StreamMetadataResult::Success(versioned) => Ok((
+                versioned.metadata().clone().into(),
+                Some(EventStreamVersion::new(versioned.version())),
+            )),
+        }
+    }
+
+    async fn set_stream_metadata(
+        &mut self,
+        stream_id: EventStreamId,
+        metadata: StreamMetadata,
+        expected_version: ExpectedVersion,
+    ) -> Result<EventStreamVersion, Error> {
+        let options = AppendToStreamOptions::default().expected_revision(expected_version.into());
+        let result = self
+            .client
+            .set_stream_metadata(stream_id.to_string().as_str(), &options, &metadata.into())
+            .await
+            .map_err(|source| stream_error(stream_id, source))?;
+        Ok(EventStreamVersion::new(result.next_expected_version))
+    }
+}
+
+pub(crate) fn kurrent_position(position: GlobalPosition) -> eventstore::Position {
+    eventstore::Position {
+        commit: position.commit(),
+        prepare: position.prepare(),
     }
 }
 
+fn subscription_filter(filter: &EventFilter) -> eventstore::SubscriptionFilter {
+    let base = match filter {
+        EventFilter::EventTypePrefix(_) => eventstore::SubscriptionFilter::on_event_type(),
+        EventFilter::StreamPrefix(_) => eventstore::SubscriptionFilter::on_stream_name(),
+    };
+    filter
+        .prefixes()
+        .iter()
+        .fold(base, |filter, prefix| filter.add_prefix(prefix))
+}
+
 pub struct EventStreamBuilder {
     store: Kurrent,
     stream_id: EventStreamId,
@@ -122,8 +345,19 @@ impl EventStreamBuilder {
         self
     }
 
-    pub fn position(mut self, position: eventstore::StreamPosition<u64>) -> Self {
-        self.read_options = self.read_options.position(position);
+    /// Starts the read at `position`, given as a mneme `StreamPosition` or a Kurrent one.
+    pub fn position(mut self, position: impl Into<eventstore::StreamPosition<u64>>) -> Self {
+        self.read_options = self.read_options.position(position.into());
+        self
+    }
+
+    /// Reads in `direction`. Reading backwards needs a position to start at, such as
+    /// `StreamPosition::End`.
+    pub fn direction(mut self, direction: ReadDirection) -> Self {
+        self.read_options = match direction {
+            ReadDirection::Forwards => self.read_options.forwards(),
+            ReadDirection::Backwards => self.read_options.backwards(),
+        };
         self
     }
 
@@ -133,17 +367,12 @@ impl EventStreamBuilder {
             .client
             .read_stream(self.stream_id.clone(), &self.read_options)
             .await
-            .map(|stream| EventStream {
-                stream,
-                type_marker: std::marker::PhantomData,
-            })
-            .map_err(|source| match source {
-                eventstore::Error::ResourceNotFound => {
-                    Error::EventStoreStreamNotFound(self.stream_id)
-                }
-                e => Error::EventStoreOther(e),
-            })?;
-        Ok(stream)
+            .map_err(|source| stream_error(self.stream_id.clone(), source))?;
+        Ok(EventStream::from_kurrent(
+            stream,
+            self.stream_id,
+            self.store.codecs.clone(),
+        ))
     }
 }
 
@@ -151,6 +380,8 @@ pub struct EventStreamWriter {
     store: Kurrent,
     stream_id: EventStreamId,
     write_options: AppendToStreamOptions,
+    metadata: EventMetadata,
+    idempotency_key: Option<String>,
 }
 
 impl EventStreamWriter {
@@ -159,6 +390,8 @@ impl EventStreamWriter {
             store,
             stream_id,
             write_options: Default::default(),
+            metadata: EventMetadata::default(),
+            idempotency_key: None,
         }
     }
 
@@ -183,13 +416,30 @@ impl EventStreamWriter {
         self
     }
 
+    /// Records `metadata` with every event appended.
+    pub fn metadata(mut self, metadata: EventMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Derives the ids of the events appended from `key`, so that if the append is retried with
+    /// the same key, events and expected version, Kurrent writes them only once.
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
     pub async fn append<E: Event>(self, events: Vec<E>) -> Result<eventstore::WriteResult, Error> {
         let events: Vec<eventstore::EventData> = events
-            .iter()
-            .map(|event| {
-                let event_type = event.event_type();
-                eventstore::EventData::json(&event_type, &event)
-                    .map_err(Error::EventDeserializationError)
+            .into_iter()
+            .enumerate()
+            .map(|(index, event)| {
+                let event = NewEvent::new(event).with_metadata(self.metadata.clone());
+                let event = match &self.idempotency_key {
+                    Some(key) => event.with_idempotency_key(key, index),
+                    None => event,
+                };
+                event_data(&event, self.store.codec.as_ref())
             })
             .collect::<Result<_, _>>()?;
 
@@ -197,27 +447,43 @@ impl EventStreamWriter {
             .client
             .append_to_stream(self.stream_id.clone(), &self.write_options, events)
             .await
-            .map_err(|source| match source {
-                eventstore::Error::ResourceNotFound => {
-                    Error::EventStoreStreamNotFound(self.stream_id)
-                }
-                eventstore::Error::WrongExpectedVersion { current, expected } => {
-                    Error::EventStoreVersionMismatch {
-                        stream: self.stream_id,
-                        expected: extract_revision(&expected),
-                        actual: extract_current_revision(&current),
-                        source,
-                    }
-                }
-                e => Error::EventStoreOther(e),
-            })
+            .map_err(|source| stream_error(self.stream_id, source))
     }
 }
 
-fn extract_revision(expected: &eventstore::ExpectedRevision) -> Option<EventStreamVersion> {
-    match expected {
-        eventstore::ExpectedRevision::Exact(v) => Some(EventStreamVersion::new(*v)),
-        _ => None,
+/// Builds the event to append to Kurrent. Kurrent only knows JSON and opaque binary payloads,
+/// so payloads written with other codecs are appended as binary and their codec is found again
+/// from the event's metadata.
+fn event_data<E: Event>(
+    event: &NewEvent<E>,
+    codec: &dyn EventCodec,
+) -> Result<eventstore::EventData, Error> {
+    let codec = event.codec(codec);
+    let event_type = event.event().event_type();
+    let event_data = if codec.content_type() == JSON_CONTENT_TYPE {
+        eventstore::EventData::json(event_type, event.event())?
+    } else {
+        eventstore::EventData::binary(event_type, codec::encode(codec, event.event())?.into())
+    };
+    Ok(event_data
+        .id(event.id())
+        .metadata_as_json(&event.stored_metadata(codec))?)
+}
+
+/// Maps a failed operation on `stream_id` to the error mneme reports for it.
+pub(crate) fn stream_error(stream_id: EventStreamId, source: eventstore::Error) -> Error {
+    match source {
+        eventstore::Error::ResourceNotFound => Error::EventStoreStreamNotFound(stream_id),
+        eventstore::Error::ResourceDeleted => Error::EventStoreStreamDeleted(stream_id),
+        eventstore::Error::WrongExpectedVersion { current, expected } => {
+            Error::EventStoreVersionMismatch {
+                stream: stream_id,
+                expected: expected.into(),
+                actual: extract_current_revision(&current),
+                source,
+            }
+        }
+        e => Error::EventStoreOther(e),
     }
 }
 
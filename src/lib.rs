@@ -1,71 +1,212 @@
+mod codec;
 mod command;
 mod config;
+mod context;
+mod dedupe;
 mod delay;
+mod deletion;
 mod error;
 mod event;
 mod event_store;
+mod event_stream;
+mod file_log_adapter;
+mod in_memory_adapter;
 mod kurrent_adapter;
+mod metadata;
+mod new_event;
+mod outcome;
+mod postgres_adapter;
+mod projection;
+mod read_all;
+mod read_stream;
+mod recorded_event;
+mod snapshot;
+mod sqlite_adapter;
+mod stream_metadata;
+mod subscription;
+#[cfg(test)]
+mod test_event;
+mod upcast;
 
-pub use command::{AggregateState, Command};
+pub use codec::{Cbor, Codecs, EventCodec, Json, MessagePack};
+pub use command::{AggregateState, AsyncCommand, Command};
 pub use config::ExecuteConfig;
-pub use error::Error;
+pub use context::CommandContext;
+pub use dedupe::DedupeIndex;
+pub use delay::{
+    ConstantBackoff, DecorrelatedJitter, ExponentialBackoff, Jitter, LinearBackoff, RetryBudget,
+    RetryPolicy,
+};
+pub use error::{Error, ExecuteError};
 pub use event::Event;
-pub use event_store::{EventStore, EventStreamId, EventStreamVersion};
-pub use kurrent_adapter::{ConnectionSettings, EventStream, Kurrent};
-
+pub use event_store::{
+    AppendResult, EventStore, EventStreamId, EventStreamVersion, ExpectedVersion, GlobalPosition,
+};
+pub use event_stream::EventStream;
+pub use file_log_adapter::{FileLog, FileLogConfig, FsyncPolicy};
+pub use in_memory_adapter::InMemoryEventStore;
+pub use kurrent_adapter::{ConnectionSettings, Kurrent};
+pub use metadata::EventMetadata;
+pub use new_event::NewEvent;
+pub use outcome::ExecuteOutcome;
+pub use postgres_adapter::Postgres;
+pub use projection::{
+    CheckpointStore, InMemoryCheckpointStore, Projection, ProjectionRunner, ProjectionStatus,
+};
+pub use read_all::{EventFilter, ReadAllOptions};
+pub use read_stream::{ReadDirection, ReadStreamOptions, StreamPosition};
+pub use recorded_event::RecordedEvent;
+pub use snapshot::{InMemorySnapshotStore, Snapshot, SnapshotState, SnapshotStore};
+pub use sqlite_adapter::Sqlite;
+pub use stream_metadata::{StreamAcl, StreamMetadata};
+pub use subscription::Subscription;
+pub use upcast::Upcasters;
+
+use command::Synchronous;
+use dedupe::KeyedEvents;
+use delay::Retries;
+use snapshot::{NoSnapshots, Snapshots, Snapshotting};
+
+/// Rebuilds the command's state from its stream, handles it and appends the events it emits,
+/// retrying from a fresh read if another writer appends to the stream first.
+///
+/// Events must be `Clone`, as the events a command emits are both appended and returned in the
+/// `ExecuteOutcome`, and are appended again when a transient failure is retried.
 pub async fn execute<E, C, S>(
     command: C,
     event_store: &mut S,
     config: ExecuteConfig,
-) -> Result<(), Error>
+) -> Result<ExecuteOutcome<E, C::State>, ExecuteError<C::Error>>
 where
-    E: Event,
+    E: Event + Clone,
     C: Command<Event = E>,
     S: EventStore,
 {
-    let mut retries = 0;
-    let mut command = command;
-
-    let result = loop {
-        if retries > config.max_retries() {
-            break Err(Error::MaxRetriesExceeded {
-                stream: command.event_stream_id().to_string(),
-                max_retries: config.max_retries(),
-            });
-        }
+    execute_inner(
+        Synchronous(command),
+        &CommandContext::default(),
+        event_store,
+        NoSnapshots,
+        config,
+    )
+    .await
+}
 
-        let mut expected_version = None;
+/// Like `execute`, but for an `AsyncCommand`, whose handler is given `context` on every
+/// attempt.
+pub async fn execute_async<E, C, S>(
+    command: C,
+    event_store: &mut S,
+    context: &CommandContext<C::Services>,
+    config: ExecuteConfig,
+) -> Result<ExecuteOutcome<E, C::State>, ExecuteError<C::Error>>
+where
+    E: Event + Clone,
+    C: AsyncCommand<Event = E>,
+    S: EventStore,
+{
+    execute_inner(command, context, event_store, NoSnapshots, config).await
+}
 
-        let read_result = event_store.read_stream(command.event_stream_id()).await;
+/// Like `execute`, but restores the command's state from the latest snapshot in
+/// `snapshot_store` and reads only the events after it. A new snapshot is saved once
+/// `ExecuteConfig::snapshot_frequency` events have been appended since the last one.
+///
+/// Snapshots written with a different `SnapshotState::SCHEMA_VERSION` are ignored and the
+/// stream is replayed from the start.
+pub async fn execute_with_snapshots<E, C, S, T>(
+    command: C,
+    event_store: &mut S,
+    snapshot_store: &mut T,
+    config: ExecuteConfig,
+) -> Result<ExecuteOutcome<E, C::State>, ExecuteError<C::Error>>
+where
+    E: Event + Clone,
+    C: Command<Event = E>,
+    C::State: SnapshotState,
+    S: EventStore,
+    T: SnapshotStore,
+{
+    let snapshots = Snapshotting {
+        store: snapshot_store,
+        frequency: config.snapshot_frequency(),
+    };
+    execute_inner(
+        Synchronous(command),
+        &CommandContext::default(),
+        event_store,
+        snapshots,
+        config,
+    )
+    .await
+}
 
-        match read_result {
-            Err(other) => {
-                break Err(other);
-            }
+async fn execute_inner<E, C, S, N>(
+    command: C,
+    context: &CommandContext<C::Services>,
+    event_store: &mut S,
+    mut snapshots: N,
+    config: ExecuteConfig,
+) -> Result<ExecuteOutcome<E, C::State>, ExecuteError<C::Error>>
+where
+    E: Event + Clone,
+    C: AsyncCommand<Event = E>,
+    S: EventStore,
+    N: Snapshots<C>,
+{
+    let mut retries = Retries::new(config.retry_policy(), config.rng_seed());
+    let mut attempts = 0;
+    // Each attempt rebuilds state on a clone of the command as given, so events applied by an
+    // attempt that lost a race aren't applied a second time.
+    let mut pristine = command;
+    // Only the command's own key identifies repeats of it. The config's key may be reused for
+    // other commands, so it only derives event ids.
+    let idempotency_key = pristine.idempotency_key();
+    let event_id_key = idempotency_key
+        .clone()
+        .or_else(|| config.idempotency_key().map(str::to_string));
+
+    loop {
+        let mut command = pristine.clone();
+
+        let restored = match snapshots.restore(&mut command).await {
+            Ok(restored) => restored,
+            Err(e) => break Err(e.into()),
+        };
 
-            Ok(mut event_stream) => {
-                while let Some((event, version)) = event_stream.next().await? {
-                    command.apply(&event);
-                    expected_version = Some(version);
+        let mut keyed = idempotency_key.as_deref().map(KeyedEvents::new);
+        let replayed = async {
+            let version =
+                replay(&mut command, event_store, restored, &config, keyed.as_mut()).await?;
+            let earlier = match keyed {
+                Some(keyed) => {
+                    earlier_events(event_store, command.event_stream_id(), keyed, &config).await?
                 }
-            }
+                None => None,
+            };
+            Ok::<_, Error>((version, earlier))
         }
+        .await;
+        let expected_version = match replayed {
+            // The command was executed before, so report what it did then.
+            Ok((_, Some(earlier))) => break Ok(earlier.into_outcome(command.get_state())),
+            Ok((version, None)) => version.or(restored),
+            Err(e) if config.retries_transient_errors() && e.is_transient() => {
+                if retries.back_off_transient().await {
+                    continue;
+                }
+                break Err(e.into());
+            }
+            Err(e) => break Err(e.into()),
+        };
 
-        let domain_events = match command.handle() {
+        attempts += 1;
+        let domain_events = match command.handle(context).await {
             Ok(events) => events,
-            Err(e) => {
-                break Err(Error::CommandFailed {
-                    message: e.to_string(),
-                    attempt: retries + 1,
-                    max_attempts: config.max_retries(),
-                    source: Box::new(e),
-                });
-            }
+            Err(e) => break Err(ExecuteError::Rejected(e)),
         };
 
         if !domain_events.is_empty() {
-            let expected_version = expected_version;
-
             #[cfg(test)]
             let expected_version = match (command.override_expected_version(), expected_version) {
                 (Some(v), _) => Some(v),
@@ -73,36 +214,275 @@ where
                 (None, None) => None,
             };
 
-            match event_store
-                .publish(command.event_stream_id(), domain_events, expected_version)
-                .await
+            let snapshot = snapshots.prepare(&command, &domain_events, restored, expected_version);
+
+            let metadata = match command.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => break Err(e.into()),
+            };
+            let events = domain_events
+                .iter()
+                .enumerate()
+                .map(|(index, event)| {
+                    let event = NewEvent::new(event.clone()).with_metadata(metadata.clone());
+                    match &event_id_key {
+                        Some(key) => event.with_idempotency_key(key, index),
+                        None => event,
+                    }
+                })
+                .collect();
+
+            match append(
+                event_store,
+                command.event_stream_id(),
+                events,
+                expected_version,
+                &config,
+                &mut retries,
+            )
+            .await
             {
-                Ok(_) => {
-                    break Ok(());
+                Ok(appended) => {
+                    let Some(first_version) = appended
+                        .version()
+                        .map_or(0, |v| v.value() + 1)
+                        .checked_sub(domain_events.len() as u64)
+                    else {
+                        break Err(Error::UnexpectedAppendVersion {
+                            stream: command.event_stream_id().to_string(),
+                            appended: domain_events.len(),
+                            version: appended.version(),
+                        }
+                        .into());
+                    };
+                    if let Some(snapshot) = snapshot {
+                        snapshots.save(snapshot).await;
+                    }
+                    for event in &domain_events {
+                        command.apply(event);
+                    }
+                    if let (Some(key), Some(index)) = (&idempotency_key, config.dedupe_index()) {
+                        index.record(
+                            &command.event_stream_id(),
+                            key,
+                            EventStreamVersion::new(first_version),
+                            domain_events.len(),
+                        );
+                    }
+                    let events = domain_events
+                        .into_iter()
+                        .zip(first_version..)
+                        .map(|(event, version)| (event, EventStreamVersion::new(version)))
+                        .collect();
+                    break Ok(ExecuteOutcome::new(
+                        events,
+                        appended.version(),
+                        appended.position(),
+                        attempts,
+                        command.get_state(),
+                    ));
                 }
                 Err(Error::EventStoreVersionMismatch { .. }) => {
-                    let delay = config.retry_delay().calculate_delay(retries);
-                    tokio::time::sleep(delay).await;
-
-                    command = command.mark_retry();
-                    retries += 1;
+                    if !retries.back_off().await {
+                        break Err(Error::MaxRetriesExceeded {
+                            stream: pristine.event_stream_id().to_string(),
+                            max_retries: retries.max_retries(),
+                            transient_retries: retries.transient(),
+                        }
+                        .into());
+                    }
+                    pristine = pristine.mark_retry();
                     continue;
                 }
                 Err(e) => {
-                    break Err(e);
+                    break Err(e.into());
                 }
             }
         }
 
-        break Ok(());
+        break Ok(ExecuteOutcome::new(
+            Vec::new(),
+            expected_version,
+            None,
+            attempts,
+            command.get_state(),
+        ));
+    }
+}
+
+/// Applies the events of the command's stream, after the snapshot it was restored from if any,
+/// and returns the version of the last one. Events appended under the command's idempotency key
+/// are collected in `keyed`.
+async fn replay<E, C, S>(
+    command: &mut C,
+    event_store: &S,
+    restored: Option<EventStreamVersion>,
+    config: &ExecuteConfig,
+    mut keyed: Option<&mut KeyedEvents<E>>,
+) -> Result<Option<EventStreamVersion>, Error>
+where
+    E: Event,
+    C: AsyncCommand<Event = E>,
+    S: EventStore,
+{
+    let read_result = match restored {
+        Some(version) => {
+            event_store
+                .read_stream_after(command.event_stream_id(), version)
+                .await
+        }
+        None => event_store.read_stream(command.event_stream_id()).await,
+    };
+    let mut event_stream = match read_result {
+        // A stream that has never been written to is simply empty.
+        Err(Error::EventStoreStreamNotFound(_)) => return Ok(None),
+        result => result?.with_upcasters(config.upcasters().clone()),
+    };
+
+    let mut last_version = None;
+    while let Some(recorded) = event_stream.next_recorded().await? {
+        command.apply(recorded.event());
+        last_version = Some(recorded.version());
+        if let Some(keyed) = keyed.as_deref_mut() {
+            keyed.observe(recorded);
+        }
+    }
+    Ok(last_version)
+}
+
+/// The events an earlier execution appended under the command's idempotency key: those the
+/// replay found, or failing that, those the config's dedupe index says were appended.
+async fn earlier_events<E, S>(
+    event_store: &S,
+    stream_id: EventStreamId,
+    replayed: KeyedEvents<E>,
+    config: &ExecuteConfig,
+) -> Result<Option<KeyedEvents<E>>, Error>
+where
+    E: Event,
+    S: EventStore,
+{
+    if !replayed.is_empty() {
+        return Ok(Some(replayed));
+    }
+    let Some((first_version, count)) = config
+        .dedupe_index()
+        .and_then(|index| index.lookup(&stream_id, replayed.key()))
+    else {
+        return Ok(None);
+    };
+
+    let read_result = match first_version.value().checked_sub(1) {
+        Some(after) => {
+            event_store
+                .read_stream_after(stream_id, EventStreamVersion::new(after))
+                .await
+        }
+        None => event_store.read_stream(stream_id).await,
+    };
+    let mut event_stream = match read_result {
+        Err(Error::EventStoreStreamNotFound(_)) => return Ok(None),
+        result => result?.with_upcasters(config.upcasters().clone()),
+    };
+
+    let mut indexed = KeyedEvents::new(replayed.key());
+    for _ in 0..count {
+        let observed = event_stream
+            .next_recorded()
+            .await?
+            .is_some_and(|recorded| indexed.observe(recorded));
+        if !observed {
+            return Ok(None);
+        }
+    }
+    Ok(Some(indexed))
+}
+
+/// Appends `events`, retrying transient failures with the same events and event ids when the
+/// config allows it.
+async fn append<E, S>(
+    event_store: &mut S,
+    stream_id: EventStreamId,
+    events: Vec<NewEvent<E>>,
+    expected_version: Option<EventStreamVersion>,
+    config: &ExecuteConfig,
+    retries: &mut Retries,
+) -> Result<AppendResult, Error>
+where
+    E: Event + Clone,
+    S: EventStore,
+{
+    // An empty stream is expected to still be empty, so that two commands creating the same
+    // aggregate can't both succeed.
+    let expected = match expected_version {
+        Some(version) => ExpectedVersion::Exact(version),
+        None => ExpectedVersion::NoStream,
     };
 
-    result
+    let mut failed_transiently = false;
+    loop {
+        match event_store
+            .append(stream_id.clone(), events.clone(), expected)
+            .await
+        {
+            Err(e) if config.retries_transient_errors() && e.is_transient() => {
+                if !retries.back_off_transient().await {
+                    return Err(e);
+                }
+                failed_transiently = true;
+            }
+            // A write that failed transiently may have been committed anyway, in which case
+            // retrying it finds the stream already moved on by these very events.
+            Err(e @ Error::EventStoreVersionMismatch { .. }) if failed_transiently => {
+                return match already_appended(event_store, stream_id, &events, expected_version)
+                    .await?
+                {
+                    Some(appended) => Ok(appended),
+                    None => Err(e),
+                };
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns where `events` were written if they are the events that follow `expected_version`.
+async fn already_appended<E, S>(
+    event_store: &S,
+    stream_id: EventStreamId,
+    events: &[NewEvent<E>],
+    expected_version: Option<EventStreamVersion>,
+) -> Result<Option<AppendResult>, Error>
+where
+    E: Event,
+    S: EventStore,
+{
+    let mut stream = match expected_version {
+        Some(version) => {
+            event_store
+                .read_stream_after::<E>(stream_id, version)
+                .await?
+        }
+        None => event_store.read_stream::<E>(stream_id).await?,
+    };
+    let mut last = None;
+    for event in events {
+        match stream.next_recorded().await? {
+            Some(recorded) if recorded.id() == event.id() => last = Some(recorded),
+            _ => return Ok(None),
+        }
+    }
+    Ok(last.map(|recorded| AppendResult::new(Some(recorded.version()), Some(recorded.position()))))
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{convert::Infallible, pin::Pin};
+    use std::{
+        convert::Infallible,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
 
     use serde::{Deserialize, Serialize};
     use uuid::Uuid;
@@ -194,29 +574,36 @@ mod tests {
         }
     }
 
-    #[tokio::test]
-    async fn command_fails_after_max_retries() {
-        let mut event_store = create_test_store();
+    async fn assert_command_fails_after_max_retries<S: EventStore>(mut event_store: S) {
         let id = Uuid::new_v4();
 
         event_store
-            .publish(EventStreamId(id), vec![TestEvent::One { id }], None)
+            .publish(
+                EventStreamId(id),
+                vec![TestEvent::One { id }],
+                ExpectedVersion::Any,
+            )
             .await
             .unwrap();
 
         for _ in 0..10 {
             event_store
-                .publish(EventStreamId(id), vec![TestEvent::One { id }], None)
+                .publish(
+                    EventStreamId(id),
+                    vec![TestEvent::One { id }],
+                    ExpectedVersion::Any,
+                )
                 .await
                 .unwrap();
         }
 
         let command = AlwaysConflictingCommand::new(id);
         match execute(command, &mut event_store, Default::default()).await {
-            Err(Error::MaxRetriesExceeded {
+            Err(ExecuteError::Failed(Error::MaxRetriesExceeded {
                 max_retries,
                 stream,
-            }) => {
+                ..
+            })) => {
                 assert_eq!(max_retries, ExecuteConfig::default().max_retries());
                 assert_eq!(stream, id.to_string());
             }
@@ -226,18 +613,367 @@ mod tests {
             ),
         }
     }
-    type OnFirstAppendFn =
-        dyn FnOnce() -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send>> + Send + Sync;
+
+    #[tokio::test]
+    async fn command_fails_after_max_retries() {
+        assert_command_fails_after_max_retries(create_test_store()).await
+    }
+
+    #[tokio::test]
+    async fn in_memory_command_fails_after_max_retries() {
+        assert_command_fails_after_max_retries(InMemoryEventStore::new()).await
+    }
+
+    #[tokio::test]
+    async fn retries_as_the_configured_policy_allows() {
+        let mut event_store = InMemoryEventStore::new();
+        let id = Uuid::new_v4();
+        for _ in 0..2 {
+            event_store
+                .publish(
+                    EventStreamId(id),
+                    vec![TestEvent::One { id }],
+                    ExpectedVersion::Any,
+                )
+                .await
+                .unwrap();
+        }
+
+        let config = ExecuteConfig::default()
+            .with_retry_policy(ConstantBackoff::new(Duration::from_millis(1), 12));
+        match execute(AlwaysConflictingCommand::new(id), &mut event_store, config).await {
+            Err(ExecuteError::Failed(Error::MaxRetriesExceeded { max_retries, .. })) => {
+                assert_eq!(max_retries, 12);
+            }
+            other => panic!("Expected max retries to be exceeded, got: {:?}", other),
+        }
+
+        let budget = RetryBudget::new(
+            ConstantBackoff::new(Duration::from_millis(1), 12),
+            2,
+            Duration::from_secs(3600),
+        );
+        let config = ExecuteConfig::default().with_retry_policy(budget.clone());
+        match execute(AlwaysConflictingCommand::new(id), &mut event_store, config).await {
+            Err(ExecuteError::Failed(Error::MaxRetriesExceeded {
+                max_retries,
+                transient_retries,
+                ..
+            })) => {
+                assert_eq!(max_retries, 12);
+                assert_eq!(transient_retries, 0);
+            }
+            other => panic!("Expected the retry budget to run out, got: {:?}", other),
+        }
+        assert_eq!(budget.remaining(), 0);
+    }
+
+    /// Records the first number drawn from the RNG for each retry, and retries twice.
+    #[derive(Debug, Clone, Default)]
+    struct RecordingPolicy {
+        draws: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl RetryPolicy for RecordingPolicy {
+        fn next_delay(
+            &self,
+            retry: u32,
+            _: Option<Duration>,
+            jitter: &mut dyn Jitter,
+        ) -> Option<Duration> {
+            self.draws.lock().unwrap().push(jitter.between(0, u64::MAX));
+            (retry < 2).then_some(Duration::from_millis(1))
+        }
+    }
+
+    #[tokio::test]
+    async fn seeded_config_jitters_retries_deterministically() {
+        let mut event_store = InMemoryEventStore::new();
+        let id = Uuid::new_v4();
+        for _ in 0..2 {
+            event_store
+                .publish(
+                    EventStreamId(id),
+                    vec![TestEvent::One { id }],
+                    ExpectedVersion::Any,
+                )
+                .await
+                .unwrap();
+        }
+
+        let mut runs = vec![];
+        for _ in 0..2 {
+            let policy = RecordingPolicy::default();
+            let config = ExecuteConfig::default()
+                .with_retry_policy(policy.clone())
+                .with_rng_seed(42);
+            let result = execute(AlwaysConflictingCommand::new(id), &mut event_store, config).await;
+            assert!(result.is_err());
+            runs.push(policy.draws.lock().unwrap().clone());
+        }
+        assert_eq!(runs[0].len(), 3);
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    /// Fails the given number of reads and appends with a transient error. Failing appends are
+    /// committed first, as when a connection drops before the store acknowledges a write.
+    struct FlakyEventStore<S> {
+        inner: S,
+        failing_reads: std::sync::atomic::AtomicU32,
+        failing_appends: u32,
+    }
+
+    impl<S> FlakyEventStore<S> {
+        fn new(inner: S, failing_reads: u32, failing_appends: u32) -> Self {
+            Self {
+                inner,
+                failing_reads: failing_reads.into(),
+                failing_appends,
+            }
+        }
+
+        fn fail_read(&self) -> Result<(), Error> {
+            use std::sync::atomic::Ordering;
+            match self.failing_reads.load(Ordering::SeqCst) {
+                0 => Ok(()),
+                n => {
+                    self.failing_reads.store(n - 1, Ordering::SeqCst);
+                    Err(Error::EventStoreOther(eventstore::Error::DeadlineExceeded))
+                }
+            }
+        }
+    }
+
+    impl<S: EventStore + Send + Sync> EventStore for FlakyEventStore<S> {
+        async fn append<E: Event>(
+            &mut self,
+            stream_id: EventStreamId,
+            events: Vec<NewEvent<E>>,
+            expected_version: ExpectedVersion,
+        ) -> Result<AppendResult, Error> {
+            let result = self.inner.append(stream_id, events, expected_version).await;
+            if result.is_ok() && self.failing_appends > 0 {
+                self.failing_appends -= 1;
+                return Err(Error::EventStoreOther(eventstore::Error::DeadlineExceeded));
+            }
+            result
+        }
+
+        async fn read_stream<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+        ) -> Result<EventStream<E>, Error> {
+            self.fail_read()?;
+            self.inner.read_stream(stream_id).await
+        }
+
+        async fn read_stream_after<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+            after: EventStreamVersion,
+        ) -> Result<EventStream<E>, Error> {
+            self.fail_read()?;
+            self.inner.read_stream_after(stream_id, after).await
+        }
+
+        async fn read_stream_with<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+            options: ReadStreamOptions,
+        ) -> Result<EventStream<E>, Error> {
+            self.fail_read()?;
+            self.inner.read_stream_with(stream_id, options).await
+        }
+
+        async fn subscribe_to_stream<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+            from: Option<EventStreamVersion>,
+        ) -> Result<Subscription<E>, Error> {
+            self.inner.subscribe_to_stream(stream_id, from).await
+        }
+
+        async fn read_all<E: Event>(
+            &self,
+            options: ReadAllOptions,
+        ) -> Result<EventStream<E>, Error> {
+            self.inner.read_all(options).await
+        }
+
+        async fn subscribe_to_all<E: Event>(
+            &self,
+            options: ReadAllOptions,
+        ) -> Result<Subscription<E>, Error> {
+            self.inner.subscribe_to_all(options).await
+        }
+
+        async fn head_position(&self) -> Result<Option<GlobalPosition>, Error> {
+            self.inner.head_position().await
+        }
+
+        async fn delete_stream(
+            &mut self,
+            stream_id: EventStreamId,
+            expected_version: ExpectedVersion,
+        ) -> Result<(), Error> {
+            self.inner.delete_stream(stream_id, expected_version).await
+        }
+
+        async fn tombstone_stream(
+            &mut self,
+            stream_id: EventStreamId,
+            expected_version: ExpectedVersion,
+        ) -> Result<(), Error> {
+            self.inner
+                .tombstone_stream(stream_id, expected_version)
+                .await
+        }
+
+        async fn truncate_stream(
+            &mut self,
+            stream_id: EventStreamId,
+            before: EventStreamVersion,
+        ) -> Result<(), Error> {
+            self.inner.truncate_stream(stream_id, before).await
+        }
+
+        async fn read_stream_metadata(
+            &self,
+            stream_id: EventStreamId,
+        ) -> Result<(StreamMetadata, Option<EventStreamVersion>), Error> {
+            self.inner.read_stream_metadata(stream_id).await
+        }
+
+        async fn set_stream_metadata(
+            &mut self,
+            stream_id: EventStreamId,
+            metadata: StreamMetadata,
+            expected_version: ExpectedVersion,
+        ) -> Result<EventStreamVersion, Error> {
+            self.inner
+                .set_stream_metadata(stream_id, metadata, expected_version)
+                .await
+        }
+    }
+
+    #[test]
+    fn classifies_transient_errors() {
+        assert!(Error::EventStoreOther(eventstore::Error::DeadlineExceeded).is_transient());
+        assert!(Error::EventStoreOther(eventstore::Error::ConnectionClosed).is_transient());
+        assert!(Error::EventStoreDatabase(sqlx::Error::PoolTimedOut).is_transient());
+        assert!(!Error::EventStoreOther(eventstore::Error::AccessDenied).is_transient());
+        assert!(!Error::EventStoreStreamNotFound(EventStreamId::new()).is_transient());
+        assert!(
+            !Error::version_mismatch(EventStreamId::new(), ExpectedVersion::NoStream, None)
+                .is_transient()
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures_without_duplicating_events() {
+        let id = Uuid::new_v4();
+        let config = ExecuteConfig::default()
+            .with_retry_policy(ConstantBackoff::new(Duration::from_millis(1), 3))
+            .with_transient_retries(true);
+
+        let mut event_store = FlakyEventStore::new(InMemoryEventStore::new(), 1, 1);
+        let outcome = execute(CountingCommand::new(id), &mut event_store, config)
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.attempts(), 1);
+        assert_eq!(outcome.version(), Some(EventStreamVersion::new(0)));
+        assert_eq!(
+            read_events(&event_store.inner, EventStreamId(id)).await,
+            vec![TestEvent::BazHappened { id, value: 0 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn max_retries_exceeded_counts_transient_retries_separately() {
+        let id = Uuid::new_v4();
+        let mut inner = InMemoryEventStore::new();
+        for _ in 0..2 {
+            inner
+                .publish(
+                    EventStreamId(id),
+                    vec![TestEvent::One { id }],
+                    ExpectedVersion::Any,
+                )
+                .await
+                .unwrap();
+        }
+        let config = ExecuteConfig::default()
+            .with_retry_policy(ConstantBackoff::new(Duration::from_millis(1), 3))
+            .with_transient_retries(true);
+
+        let mut event_store = FlakyEventStore::new(inner, 1, 0);
+        match execute(AlwaysConflictingCommand::new(id), &mut event_store, config).await {
+            Err(ExecuteError::Failed(Error::MaxRetriesExceeded {
+                max_retries,
+                transient_retries,
+                ..
+            })) => {
+                assert_eq!(max_retries, 3);
+                assert_eq!(transient_retries, 1);
+            }
+            other => panic!("Expected max retries to be exceeded, got: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_failures_fail_the_command_by_default() {
+        let id = Uuid::new_v4();
+        let mut event_store = FlakyEventStore::new(InMemoryEventStore::new(), 1, 0);
+
+        match execute(
+            CountingCommand::new(id),
+            &mut event_store,
+            Default::default(),
+        )
+        .await
+        {
+            Err(ExecuteError::Failed(e)) => assert!(e.is_transient()),
+            other => panic!("Expected a transient failure, got: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn idempotency_key_derives_the_ids_of_appended_events() {
+        let id = Uuid::new_v4();
+        let config = ExecuteConfig::default().with_idempotency_key("withdrawal-42");
+
+        let mut event_store = InMemoryEventStore::new();
+        execute(CountingCommand::new(id), &mut event_store, config)
+            .await
+            .unwrap();
+
+        let mut stream = event_store
+            .read_stream::<TestEvent>(EventStreamId(id))
+            .await
+            .unwrap();
+        let recorded = stream.next_recorded().await.unwrap().unwrap();
+        assert_eq!(
+            recorded.id(),
+            NewEvent::new(())
+                .with_idempotency_key("withdrawal-42", 0)
+                .id()
+        );
+    }
+
+    type OnFirstAppendFn = dyn FnOnce() -> Pin<Box<dyn Future<Output = Result<AppendResult, Error>> + Send>>
+        + Send
+        + Sync;
 
     /// A test helper that intercepts event store operations for testing concurrent modifications
-    struct TestEventStore {
-        inner: Kurrent,
+    struct TestEventStore<S: EventStore> {
+        inner: S,
         on_first_append: Option<Box<OnFirstAppendFn>>,
         has_appended: bool,
     }
 
-    impl TestEventStore {
-        fn new(inner: Kurrent) -> Self {
+    impl<S: EventStore> TestEventStore<S> {
+        fn new(inner: S) -> Self {
             Self {
                 inner,
                 on_first_append: None,
@@ -248,75 +984,121 @@ mod tests {
         fn on_first_append<F, Fut>(&mut self, f: F)
         where
             F: FnOnce() -> Fut + Send + Sync + 'static,
-            Fut: Future<Output = Result<(), Error>> + Send + 'static,
+            Fut: Future<Output = Result<AppendResult, Error>> + Send + 'static,
         {
             self.on_first_append = Some(Box::new(move || Box::pin(f())));
         }
+    }
+
+    impl<S: EventStore + Send + Sync> EventStore for TestEventStore<S> {
+        async fn append<E: Event>(
+            &mut self,
+            stream_id: EventStreamId,
+            events: Vec<NewEvent<E>>,
+            expected_version: ExpectedVersion,
+        ) -> Result<AppendResult, Error> {
+            // If we have a hook and this is the first append, run it before continuing
+            if !self.has_appended {
+                self.has_appended = true;
+                if let Some(hook) = self.on_first_append.take() {
+                    let fut = hook();
+                    fut.await?;
+                }
+            }
+            self.inner.append(stream_id, events, expected_version).await
+        }
+
+        async fn read_stream<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+        ) -> Result<EventStream<E>, Error> {
+            self.inner.read_stream(stream_id).await
+        }
+
+        async fn read_stream_after<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+            after: EventStreamVersion,
+        ) -> Result<EventStream<E>, Error> {
+            self.inner.read_stream_after(stream_id, after).await
+        }
+
+        async fn read_stream_with<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+            options: ReadStreamOptions,
+        ) -> Result<EventStream<E>, Error> {
+            self.inner.read_stream_with(stream_id, options).await
+        }
+
+        async fn subscribe_to_stream<E: Event>(
+            &self,
+            stream_id: EventStreamId,
+            from: Option<EventStreamVersion>,
+        ) -> Result<Subscription<E>, Error> {
+            self.inner.subscribe_to_stream(stream_id, from).await
+        }
+
+        async fn read_all<E: Event>(
+            &self,
+            options: ReadAllOptions,
+        ) -> Result<EventStream<E>, Error> {
+            self.inner.read_all(options).await
+        }
+
+        async fn subscribe_to_all<E: Event>(
+            &self,
+            options: ReadAllOptions,
+        ) -> Result<Subscription<E>, Error> {
+            self.inner.subscribe_to_all(options).await
+        }
 
-        async fn append_to_stream(
+        async fn head_position(&self) -> Result<Option<GlobalPosition>, Error> {
+            self.inner.head_position().await
+        }
+
+        async fn delete_stream(
             &mut self,
             stream_id: EventStreamId,
-            expected_version: Option<EventStreamVersion>,
-            events: Vec<eventstore::EventData>,
-        ) -> Result<eventstore::WriteResult, Error> {
-            // If we have a hook and this is the first append, run it before continuing
-            if !self.has_appended {
-                self.has_appended = true;
-                if let Some(hook) = self.on_first_append.take() {
-                    let fut = hook();
-                    fut.await?;
-                }
-            }
-            let options = eventstore::AppendToStreamOptions::default().expected_revision(
-                match expected_version {
-                    Some(v) => eventstore::ExpectedRevision::Exact(v.value()),
-                    None => eventstore::ExpectedRevision::Any,
-                },
-            );
+            expected_version: ExpectedVersion,
+        ) -> Result<(), Error> {
+            self.inner.delete_stream(stream_id, expected_version).await
+        }
+
+        async fn tombstone_stream(
+            &mut self,
+            stream_id: EventStreamId,
+            expected_version: ExpectedVersion,
+        ) -> Result<(), Error> {
             self.inner
-                .append_to_stream(stream_id, &options, events)
+                .tombstone_stream(stream_id, expected_version)
                 .await
         }
-    }
 
-    impl EventStore for TestEventStore {
-        async fn publish<E: Event>(
+        async fn truncate_stream(
             &mut self,
             stream_id: EventStreamId,
-            events: Vec<E>,
-            expected_version: Option<EventStreamVersion>,
+            before: EventStreamVersion,
         ) -> Result<(), Error> {
-            let events: Vec<eventstore::EventData> = events
-                .iter()
-                .map(|event| {
-                    eventstore::EventData::json(event.event_type(), &event)
-                        .expect("unable to serialize event")
-                })
-                .collect();
-            self.append_to_stream(stream_id, expected_version, events)
-                .await?;
-            Ok(())
+            self.inner.truncate_stream(stream_id, before).await
         }
 
-        async fn read_stream<E: Event>(
+        async fn read_stream_metadata(
             &self,
             stream_id: EventStreamId,
-        ) -> Result<EventStream<E>, Error> {
-            self.inner.read_stream(stream_id).await
-        }
-    }
-
-    impl std::ops::Deref for TestEventStore {
-        type Target = Kurrent;
-
-        fn deref(&self) -> &Self::Target {
-            &self.inner
+        ) -> Result<(StreamMetadata, Option<EventStreamVersion>), Error> {
+            self.inner.read_stream_metadata(stream_id).await
         }
-    }
 
-    impl std::ops::DerefMut for TestEventStore {
-        fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.inner
+        async fn set_stream_metadata(
+            &mut self,
+            stream_id: EventStreamId,
+            metadata: StreamMetadata,
+            expected_version: ExpectedVersion,
+        ) -> Result<EventStreamVersion, Error> {
+            self.inner
+                .set_stream_metadata(stream_id, metadata, expected_version)
+                .await
         }
     }
 
@@ -391,9 +1173,11 @@ mod tests {
             self
         }
     }
-    #[tokio::test]
-    async fn retries_on_append_version_mismatch() {
-        let mut event_store = create_test_store();
+
+    async fn assert_retries_on_append_version_mismatch<S>(mut event_store: S)
+    where
+        S: EventStore + Clone + Send + Sync + 'static,
+    {
         let id = Uuid::new_v4();
 
         let initial_events = vec![
@@ -401,28 +1185,29 @@ mod tests {
             TestEvent::BarHappened { id, value: 24 },
         ];
         event_store
-            .publish(EventStreamId(id), initial_events, None)
+            .publish(EventStreamId(id), initial_events, ExpectedVersion::Any)
             .await
             .unwrap();
 
+        let store_for_hook = event_store.clone();
         let mut test_store = TestEventStore::new(event_store);
-        let store_for_hook = test_store.inner.clone();
 
         test_store.on_first_append(move || {
             let concurrent_event = vec![TestEvent::FooHappened { id, value: 100 }];
             let mut store = store_for_hook;
             async move {
                 store
-                    .publish(EventStreamId(id), concurrent_event, None)
+                    .publish(EventStreamId(id), concurrent_event, ExpectedVersion::Any)
                     .await
             }
         });
 
         let command = ConcurrentModificationCommand::new(id);
         match execute(command, &mut test_store, Default::default()).await {
-            Ok(()) => {
+            Ok(outcome) => {
+                assert_eq!(outcome.attempts(), 2);
                 assert_eq!(
-                    read_client_events(&test_store.client, EventStreamId(id)).await,
+                    read_events(&test_store, EventStreamId(id)).await,
                     vec![
                         TestEvent::FooHappened { id, value: 42 },
                         TestEvent::BarHappened { id, value: 24 },
@@ -435,26 +1220,581 @@ mod tests {
         }
     }
 
-    async fn read_client_events(
-        client: &eventstore::Client,
+    #[tokio::test]
+    async fn retries_on_append_version_mismatch() {
+        assert_retries_on_append_version_mismatch(create_test_store()).await
+    }
+
+    #[tokio::test]
+    async fn in_memory_retries_on_append_version_mismatch() {
+        assert_retries_on_append_version_mismatch(InMemoryEventStore::new()).await
+    }
+
+    #[tokio::test]
+    async fn creating_a_stream_conflicts_with_a_concurrent_creation() {
+        let event_store = InMemoryEventStore::new();
+        let id = Uuid::new_v4();
+
+        let mut store_for_hook = event_store.clone();
+        let mut test_store = TestEventStore::new(event_store);
+        test_store.on_first_append(move || async move {
+            store_for_hook
+                .publish(
+                    EventStreamId(id),
+                    vec![TestEvent::One { id }],
+                    ExpectedVersion::NoStream,
+                )
+                .await
+        });
+
+        execute(
+            CountingCommand::new(id),
+            &mut test_store,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        // The first attempt expected no stream and lost to the concurrent creation, so the retry
+        // counted its event.
+        assert_eq!(
+            read_events(&test_store, EventStreamId(id)).await,
+            vec![
+                TestEvent::One { id },
+                TestEvent::BazHappened { id, value: 1 }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rebuilds_state_from_scratch_on_retry() {
+        let mut event_store = InMemoryEventStore::new();
+        let id = Uuid::new_v4();
+        event_store
+            .publish(
+                EventStreamId(id),
+                vec![TestEvent::One { id }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+
+        let mut store_for_hook = event_store.clone();
+        let mut test_store = TestEventStore::new(event_store);
+        test_store.on_first_append(move || async move {
+            store_for_hook
+                .publish(
+                    EventStreamId(id),
+                    vec![TestEvent::Two { id }],
+                    ExpectedVersion::Any,
+                )
+                .await
+        });
+
+        let outcome = execute(
+            CountingCommand::new(id),
+            &mut test_store,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome.attempts(), 2);
+
+        // The retry saw both earlier events once each, not the first one twice.
+        assert_eq!(
+            read_events(&test_store, EventStreamId(id)).await.last(),
+            Some(&TestEvent::BazHappened { id, value: 2 })
+        );
+    }
+
+    #[derive(Default)]
+    struct PricingService {
+        quotes: std::sync::atomic::AtomicU32,
+    }
+
+    impl PricingService {
+        async fn quote(&self) -> u32 {
+            tokio::task::yield_now().await;
+            self.quotes
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                + 10
+        }
+    }
+
+    #[derive(Clone)]
+    struct PricedCommand {
+        id: Uuid,
+    }
+
+    impl AsyncCommand for PricedCommand {
+        type Event = TestEvent;
+        type State = ();
+        type Error = Infallible;
+        type Services = PricingService;
+
+        async fn handle(
+            &self,
+            context: &CommandContext<PricingService>,
+        ) -> Result<Vec<TestEvent>, Infallible> {
+            Ok(vec![TestEvent::BazHappened {
+                id: self.id,
+                value: context.services().quote().await,
+            }])
+        }
+        fn event_stream_id(&self) -> EventStreamId {
+            EventStreamId(self.id)
+        }
+        fn get_state(&self) -> Self::State {}
+        fn set_state(&mut self, _: &Self::State) {}
+    }
+
+    #[tokio::test]
+    async fn async_commands_call_services_on_every_attempt() {
+        let event_store = InMemoryEventStore::new();
+        let id = Uuid::new_v4();
+
+        let mut store_for_hook = event_store.clone();
+        let mut test_store = TestEventStore::new(event_store);
+        test_store.on_first_append(move || async move {
+            store_for_hook
+                .publish(
+                    EventStreamId(id),
+                    vec![TestEvent::One { id }],
+                    ExpectedVersion::Any,
+                )
+                .await
+        });
+
+        let context = CommandContext::new(PricingService::default());
+        let outcome = execute_async(
+            PricedCommand { id },
+            &mut test_store,
+            &context,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(outcome.attempts(), 2);
+        assert_eq!(
+            outcome.events(),
+            &[(
+                TestEvent::BazHappened { id, value: 11 },
+                EventStreamVersion::new(1)
+            )]
+        );
+    }
+
+    async fn read_events<S: EventStore>(
+        event_store: &S,
         stream_id: EventStreamId,
     ) -> Vec<TestEvent> {
-        let mut stream = client
-            .read_stream(stream_id.clone(), &Default::default())
+        let mut stream = event_store
+            .read_stream::<TestEvent>(stream_id)
             .await
             .expect("failed to read stream");
         let mut events = vec![];
-        while let Some(event) = stream.next().await.expect("failed to get next event") {
-            events.push(
-                event
-                    .get_original_event()
-                    .as_json::<TestEvent>()
-                    .expect("failed to deserialize event"),
-            );
+        while let Some((event, _)) = stream.next().await.expect("failed to get next event") {
+            events.push(event);
         }
         events
     }
 
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    struct CountingState {
+        count: u32,
+    }
+
+    impl AggregateState<TestEvent> for CountingState {
+        fn apply(&mut self, _: &TestEvent) -> &Self {
+            self.count += 1;
+            self
+        }
+    }
+
+    impl SnapshotState for CountingState {
+        const SCHEMA_VERSION: u32 = 1;
+    }
+
+    /// Appends an event recording how many events its state had seen.
+    #[derive(Clone)]
+    struct CountingCommand {
+        id: Uuid,
+        key: Option<String>,
+        state: CountingState,
+    }
+
+    impl CountingCommand {
+        fn new(id: Uuid) -> Self {
+            Self {
+                id,
+                key: None,
+                state: CountingState::default(),
+            }
+        }
+
+        fn keyed(id: Uuid, key: &str) -> Self {
+            Self {
+                key: Some(key.to_string()),
+                ..Self::new(id)
+            }
+        }
+    }
+
+    impl Command for CountingCommand {
+        type Event = TestEvent;
+        type State = CountingState;
+        type Error = Infallible;
+
+        fn get_state(&self) -> Self::State {
+            self.state.clone()
+        }
+
+        fn set_state(&mut self, state: &Self::State) {
+            self.state = state.clone();
+        }
+
+        fn event_stream_id(&self) -> EventStreamId {
+            EventStreamId(self.id)
+        }
+
+        fn handle(&self) -> Result<Vec<TestEvent>, Self::Error> {
+            Ok(vec![TestEvent::BazHappened {
+                id: self.id,
+                value: self.state.count,
+            }])
+        }
+
+        fn idempotency_key(&self) -> Option<String> {
+            self.key.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn saves_snapshot_every_n_events() {
+        let mut event_store = InMemoryEventStore::new();
+        let mut snapshots = InMemorySnapshotStore::new();
+        let id = Uuid::new_v4();
+        let config = ExecuteConfig::default().with_snapshot_frequency(3).unwrap();
+
+        let mut snapshot_versions = vec![];
+        for _ in 0..7 {
+            execute_with_snapshots(
+                CountingCommand::new(id),
+                &mut event_store,
+                &mut snapshots,
+                config.clone(),
+            )
+            .await
+            .unwrap();
+            let snapshot = snapshots.load(&EventStreamId(id)).await.unwrap();
+            snapshot_versions.push(snapshot.map(|s| s.version().value()));
+        }
+
+        assert_eq!(
+            snapshot_versions,
+            vec![None, None, Some(2), Some(2), Some(2), Some(5), Some(5)]
+        );
+        let snapshot = snapshots.load(&EventStreamId(id)).await.unwrap().unwrap();
+        assert_eq!(snapshot.state(), &serde_json::json!({ "count": 6 }));
+    }
+
+    #[tokio::test]
+    async fn dedupe_index_finds_repeats_behind_snapshots() {
+        let mut event_store = InMemoryEventStore::new();
+        let mut snapshots = InMemorySnapshotStore::new();
+        let id = Uuid::new_v4();
+        let index = DedupeIndex::new(Duration::from_secs(60));
+        let config = |index: Option<&DedupeIndex>| {
+            let config = ExecuteConfig::default().with_snapshot_frequency(1).unwrap();
+            match index {
+                Some(index) => config.with_dedupe_index(index.clone()),
+                None => config,
+            }
+        };
+
+        for key in ["first", "second"] {
+            execute_with_snapshots(
+                CountingCommand::keyed(id, key),
+                &mut event_store,
+                &mut snapshots,
+                config(Some(&index)),
+            )
+            .await
+            .unwrap();
+        }
+
+        let outcome = execute_with_snapshots(
+            CountingCommand::keyed(id, "first"),
+            &mut event_store,
+            &mut snapshots,
+            config(Some(&index)),
+        )
+        .await
+        .unwrap();
+        assert!(outcome.is_duplicate());
+        assert_eq!(
+            outcome.events(),
+            &[(
+                TestEvent::BazHappened { id, value: 0 },
+                EventStreamVersion::new(0)
+            )]
+        );
+        assert_eq!(outcome.state().count, 2);
+
+        // Without the index, the snapshot hides the first command's events.
+        let outcome = execute_with_snapshots(
+            CountingCommand::keyed(id, "first"),
+            &mut event_store,
+            &mut snapshots,
+            config(None),
+        )
+        .await
+        .unwrap();
+        assert!(!outcome.is_duplicate());
+    }
+
+    #[tokio::test]
+    async fn config_idempotency_key_does_not_deduplicate_other_commands() {
+        let mut event_store = InMemoryEventStore::new();
+        let id = Uuid::new_v4();
+        let config = ExecuteConfig::default().with_idempotency_key("batch-7");
+
+        for count in 0..2 {
+            let outcome = execute(CountingCommand::new(id), &mut event_store, config.clone())
+                .await
+                .unwrap();
+            assert!(!outcome.is_duplicate());
+            assert_eq!(outcome.state().count, count + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn restores_state_from_snapshot_and_reads_only_later_events() {
+        let mut event_store = InMemoryEventStore::new();
+        let mut snapshots = InMemorySnapshotStore::new();
+        let id = Uuid::new_v4();
+
+        event_store
+            .publish(
+                EventStreamId(id),
+                vec![
+                    TestEvent::One { id },
+                    TestEvent::Two { id },
+                    TestEvent::One { id },
+                ],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+        // Deliberately inconsistent with the stream, to show which events were replayed.
+        snapshots
+            .save(Snapshot::new(
+                EventStreamId(id),
+                EventStreamVersion::new(1),
+                CountingState::SCHEMA_VERSION,
+                serde_json::json!({ "count": 40 }),
+            ))
+            .await
+            .unwrap();
+
+        execute_with_snapshots(
+            CountingCommand::new(id),
+            &mut event_store,
+            &mut snapshots,
+            ExecuteConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            read_events(&event_store, EventStreamId(id)).await.last(),
+            Some(&TestEvent::BazHappened { id, value: 41 })
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_snapshot_with_other_schema_version() {
+        let mut event_store = InMemoryEventStore::new();
+        let mut snapshots = InMemorySnapshotStore::new();
+        let id = Uuid::new_v4();
+
+        event_store
+            .publish(
+                EventStreamId(id),
+                vec![TestEvent::One { id }, TestEvent::Two { id }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+        snapshots
+            .save(Snapshot::new(
+                EventStreamId(id),
+                EventStreamVersion::new(1),
+                CountingState::SCHEMA_VERSION + 1,
+                serde_json::json!({ "count": 40 }),
+            ))
+            .await
+            .unwrap();
+
+        execute_with_snapshots(
+            CountingCommand::new(id),
+            &mut event_store,
+            &mut snapshots,
+            ExecuteConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            read_events(&event_store, EventStreamId(id)).await.last(),
+            Some(&TestEvent::BazHappened { id, value: 2 })
+        );
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct RequestContext {
+        tenant: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    enum LegacyEvent {
+        Baz { id: Uuid, count: u32 },
+    }
+
+    impl Event for LegacyEvent {
+        fn event_type(&self) -> String {
+            "TestEvent.BazHappened".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn upcasts_stream_before_handling_command() {
+        let mut event_store = InMemoryEventStore::new();
+        let id = Uuid::new_v4();
+        event_store
+            .publish(
+                EventStreamId(id),
+                vec![LegacyEvent::Baz { id, count: 7 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+
+        let result = execute(
+            CountingCommand::new(id),
+            &mut event_store,
+            ExecuteConfig::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(ExecuteError::Failed(Error::EventDeserializationError(_)))
+        ));
+
+        let upcasters = Upcasters::new().register("TestEvent.BazHappened", 1, |mut payload| {
+            match payload.get_mut("Baz").map(serde_json::Value::take) {
+                Some(mut baz) => {
+                    baz["value"] = baz["count"].take();
+                    serde_json::json!({ "BazHappened": baz })
+                }
+                None => payload,
+            }
+        });
+        execute(
+            CountingCommand::new(id),
+            &mut event_store,
+            ExecuteConfig::default().with_upcasters(upcasters.clone()),
+        )
+        .await
+        .unwrap();
+
+        let mut stream = event_store
+            .read_stream::<TestEvent>(EventStreamId(id))
+            .await
+            .unwrap()
+            .with_upcasters(upcasters);
+        assert_eq!(
+            stream.next().await.unwrap(),
+            Some((
+                TestEvent::BazHappened { id, value: 7 },
+                EventStreamVersion::new(0)
+            ))
+        );
+        assert_eq!(
+            stream.next().await.unwrap(),
+            Some((
+                TestEvent::BazHappened { id, value: 1 },
+                EventStreamVersion::new(1)
+            ))
+        );
+    }
+
+    #[derive(Clone)]
+    struct CorrelatedCommand {
+        id: Uuid,
+        correlation_id: Uuid,
+    }
+
+    impl Command for CorrelatedCommand {
+        type Event = TestEvent;
+        type State = ();
+        type Error = Infallible;
+
+        fn handle(&self) -> Result<Vec<TestEvent>, Self::Error> {
+            Ok(vec![
+                TestEvent::One { id: self.id },
+                TestEvent::Two { id: self.id },
+            ])
+        }
+        fn event_stream_id(&self) -> EventStreamId {
+            EventStreamId(self.id)
+        }
+        fn get_state(&self) -> Self::State {}
+        fn set_state(&mut self, _: &Self::State) {}
+        fn metadata(&self) -> Result<EventMetadata, Error> {
+            EventMetadata::new()
+                .with_correlation_id(self.correlation_id)
+                .with_custom(&RequestContext {
+                    tenant: "acme".to_string(),
+                })
+        }
+    }
+
+    #[tokio::test]
+    async fn records_command_metadata_with_each_event() {
+        let mut event_store = InMemoryEventStore::new();
+        let command = CorrelatedCommand {
+            id: Uuid::new_v4(),
+            correlation_id: Uuid::new_v4(),
+        };
+
+        execute(command.clone(), &mut event_store, Default::default())
+            .await
+            .unwrap();
+
+        let mut stream = event_store
+            .read_stream::<TestEvent>(EventStreamId(command.id))
+            .await
+            .unwrap();
+        let mut ids = vec![];
+        while let Some(recorded) = stream.next_recorded().await.unwrap() {
+            assert_eq!(
+                recorded.metadata().correlation_id(),
+                Some(command.correlation_id)
+            );
+            assert_eq!(
+                recorded.metadata().custom::<RequestContext>().unwrap(),
+                RequestContext {
+                    tenant: "acme".to_string()
+                }
+            );
+            ids.push(recorded.id());
+        }
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+    }
+
     #[derive(Clone)]
     struct EventProducingCommand {
         id: Uuid,
@@ -484,7 +1824,7 @@ mod tests {
         let command = EventProducingCommand { id: Uuid::new_v4() };
 
         match execute(command, &mut event_store, Default::default()).await {
-            Err(Error::EventStoreOther(source)) => {
+            Err(ExecuteError::Failed(Error::EventStoreOther(source))) => {
                 assert!(source.to_string().contains("gRPC connection error"));
             }
             other => panic!("Expected EventStoreOther error, got {:?}", other),
@@ -527,7 +1867,10 @@ mod tests {
                 source: _,
             }) => {
                 assert_eq!(stream, stream_id);
-                assert_eq!(expected, Some(EventStreamVersion::new(99)));
+                assert_eq!(
+                    expected,
+                    ExpectedVersion::Exact(EventStreamVersion::new(99))
+                );
                 assert!(actual.is_some()); // the actual version should be available
             }
             other => panic!("Expected version mismatch error, got: {:?}", other),
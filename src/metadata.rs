@@ -0,0 +1,241 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::codec::{EventCodec, JSON_CONTENT_TYPE};
+use crate::error::Error;
+use crate::recorded_event::RecordedEvent;
+
+/// Metadata recorded alongside an event.
+///
+/// Correlation and causation ids are stored under the `$correlationId` and `$causationId`
+/// keys Kurrent's own projections understand. Any other keys are user metadata.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventMetadata {
+    #[serde(
+        rename = "$correlationId",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    correlation_id: Option<Uuid>,
+    #[serde(
+        rename = "$causationId",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    causation_id: Option<Uuid>,
+    #[serde(
+        rename = "$schemaVersion",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    schema_version: Option<u32>,
+    #[serde(
+        rename = "$contentType",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    content_type: Option<String>,
+    #[serde(flatten)]
+    values: serde_json::Map<String, serde_json::Value>,
+}
+
+impl EventMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Metadata for events written in response to `event`: they are caused by it and share its
+    /// correlation id, or take its id as their correlation id if it has none.
+    pub fn caused_by<E>(event: &RecordedEvent<E>) -> Self {
+        Self {
+            correlation_id: Some(
+                event
+                    .metadata()
+                    .correlation_id
+                    .unwrap_or_else(|| event.id()),
+            ),
+            causation_id: Some(event.id()),
+            schema_version: None,
+            content_type: None,
+            values: Default::default(),
+        }
+    }
+
+    /// Identifies the overall operation an event is part of, typically the id of the request
+    /// that started it.
+    pub fn with_correlation_id(mut self, id: Uuid) -> Self {
+        self.correlation_id = Some(id);
+        self
+    }
+
+    /// Identifies the message, such as a command or another event, that directly caused an
+    /// event.
+    pub fn with_causation_id(mut self, id: Uuid) -> Self {
+        self.causation_id = Some(id);
+        self
+    }
+
+    /// Adds a user metadata value. Keys starting with `$` are reserved for the ids and settings
+    /// recorded alongside it, so they are rejected.
+    pub fn with_value(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Result<Self, Error> {
+        let key = key.into();
+        check_key(&key)?;
+        self.values.insert(key, value.into());
+        Ok(self)
+    }
+
+    /// Adds the fields of `custom`, which must serialize to a JSON object without `$` keys, as
+    /// user metadata. Fields already present are replaced.
+    pub fn with_custom<M: Serialize>(mut self, custom: &M) -> Result<Self, Error> {
+        let serde_json::Value::Object(fields) = serde_json::to_value(custom)? else {
+            return Err(Error::InvalidConfig {
+                message: "custom metadata must serialize to a JSON object".to_string(),
+                parameter: None,
+            });
+        };
+        for key in fields.keys() {
+            check_key(key)?;
+        }
+        self.values.extend(fields);
+        Ok(self)
+    }
+
+    /// Reads the user metadata back as `M`. Keys `M` doesn't know about are ignored unless it
+    /// denies unknown fields.
+    pub fn custom<M: DeserializeOwned>(&self) -> Result<M, Error> {
+        Ok(serde_json::from_value(serde_json::Value::Object(
+            self.values.clone(),
+        ))?)
+    }
+
+    pub fn correlation_id(&self) -> Option<Uuid> {
+        self.correlation_id
+    }
+
+    pub fn causation_id(&self) -> Option<Uuid> {
+        self.causation_id
+    }
+
+    /// The `Event::schema_version` the event was recorded with, if it was recorded with one.
+    pub fn schema_version(&self) -> Option<u32> {
+        self.schema_version
+    }
+
+    /// The content type of the event's payload, if it was written with a codec other than
+    /// JSON.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn value(&self, key: &str) -> Option<&serde_json::Value> {
+        self.values.get(key)
+    }
+
+    pub(crate) fn with_schema_version(mut self, version: u32) -> Self {
+        self.schema_version = Some(version);
+        self
+    }
+
+    /// Records the codec the payload was written with. JSON is left unrecorded, as it is for
+    /// every event written before codecs could be chosen.
+    pub(crate) fn with_codec(mut self, codec: &dyn EventCodec) -> Self {
+        let content_type = codec.content_type();
+        self.content_type = (content_type != JSON_CONTENT_TYPE).then(|| content_type.to_string());
+        self
+    }
+}
+
+/// Rejects user metadata keys starting with `$`.
+fn check_key(key: &str) -> Result<(), Error> {
+    if key.starts_with('$') {
+        return Err(Error::InvalidConfig {
+            message: format!("event metadata key '{key}' is reserved"),
+            parameter: Some(key.to_string()),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_ids_under_kurrent_keys() {
+        let correlation_id = Uuid::new_v4();
+        let causation_id = Uuid::new_v4();
+        let metadata = EventMetadata::new()
+            .with_correlation_id(correlation_id)
+            .with_causation_id(causation_id)
+            .with_value("tenant", "acme")
+            .unwrap();
+
+        let json = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "$correlationId": correlation_id,
+                "$causationId": causation_id,
+                "tenant": "acme",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<EventMetadata>(json).unwrap(),
+            metadata
+        );
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct RequestContext {
+        tenant: String,
+        user_id: u32,
+    }
+
+    #[test]
+    fn round_trips_custom_metadata() {
+        let context = RequestContext {
+            tenant: "acme".to_string(),
+            user_id: 7,
+        };
+        let metadata = EventMetadata::new()
+            .with_correlation_id(Uuid::new_v4())
+            .with_custom(&context)
+            .unwrap();
+
+        assert_eq!(metadata.value("tenant"), Some(&serde_json::json!("acme")));
+        assert_eq!(metadata.custom::<RequestContext>().unwrap(), context);
+    }
+
+    #[test]
+    fn rejects_custom_metadata_that_is_not_an_object() {
+        assert!(matches!(
+            EventMetadata::new().with_custom(&42),
+            Err(Error::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_reserved_keys() {
+        assert!(matches!(
+            EventMetadata::new().with_value("$correlationId", "acme"),
+            Err(Error::InvalidConfig { .. })
+        ));
+        assert!(matches!(
+            EventMetadata::new().with_custom(&serde_json::json!({ "$schemaVersion": 2 })),
+            Err(Error::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn empty_metadata_serializes_to_empty_object() {
+        assert_eq!(
+            serde_json::to_value(EventMetadata::new()).unwrap(),
+            serde_json::json!({})
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use uuid::Uuid;
+
+use crate::codec::{self, EventCodec};
+use crate::error::Error;
+use crate::event::Event;
+use crate::metadata::EventMetadata;
+
+/// The namespace of the v5 ids derived from idempotency keys.
+const IDEMPOTENCY_NAMESPACE: Uuid = Uuid::from_u128(0x6d6e_656d_65e1_4f3a_9c1d_3b7a_2f5e_8d40);
+
+/// An event to be appended, along with the id and metadata to record it with.
+#[derive(Debug, Clone)]
+pub struct NewEvent<E> {
+    id: Uuid,
+    event: E,
+    metadata: EventMetadata,
+}
+
+impl<E> NewEvent<E> {
+    /// Wraps `event` with a fresh id and no metadata.
+    pub fn new(event: E) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event,
+            metadata: EventMetadata::default(),
+        }
+    }
+
+    pub fn with_id(mut self, id: Uuid) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Gives the event an id derived from `key` and its `index` in the batch being appended, so
+    /// appending the same batch under the same key again is a no-op rather than a duplicate.
+    /// The id doesn't depend on the stream, so it is only unique within the stream appended to.
+    pub fn with_idempotency_key(self, key: &str, index: usize) -> Self {
+        self.with_id(idempotent_id(key, index))
+    }
+
+    pub fn with_metadata(mut self, metadata: EventMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn event(&self) -> &E {
+        &self.event
+    }
+
+    pub fn metadata(&self) -> &EventMetadata {
+        &self.metadata
+    }
+}
+
+impl<E: Event> NewEvent<E> {
+    /// The event's own codec, or `default` if it doesn't name one.
+    pub(crate) fn codec<'a>(&self, default: &'a dyn EventCodec) -> &'a dyn EventCodec {
+        self.event.codec().unwrap_or(default)
+    }
+
+    /// The metadata to store with the event, including its schema version and codec.
+    pub(crate) fn stored_metadata(&self, codec: &dyn EventCodec) -> EventMetadata {
+        self.metadata
+            .clone()
+            .with_schema_version(self.event.schema_version())
+            .with_codec(codec)
+    }
+
+    /// Serializes the event with `codec` as chosen by `codec()`, returning the payload and the
+    /// metadata to store with it.
+    pub(crate) fn encode(
+        &self,
+        default: &dyn EventCodec,
+    ) -> Result<(Vec<u8>, EventMetadata), Error> {
+        let codec = self.codec(default);
+        Ok((
+            codec::encode(codec, &self.event)?,
+            self.stored_metadata(codec),
+        ))
+    }
+}
+
+/// The id of the event at `index` in a batch appended under idempotency key `key`.
+///
+/// The stream isn't part of the id, so batches appended to different streams under the same key
+/// share their ids. Appends are only recognized as replays within a stream, so keys need only be
+/// unique per stream, but ids derived from them are not unique across the store.
+pub(crate) fn idempotent_id(key: &str, index: usize) -> Uuid {
+    Uuid::new_v5(&IDEMPOTENCY_NAMESPACE, format!("{key}/{index}").as_bytes())
+}
+
+impl<E> From<E> for NewEvent<E> {
+    fn from(event: E) -> Self {
+        Self::new(event)
+    }
+}
@@ -0,0 +1,73 @@
+use crate::event_store::{EventStreamVersion, GlobalPosition};
+
+/// What a successful `execute` did to the command's stream.
+#[derive(Debug, Clone)]
+pub struct ExecuteOutcome<E, S> {
+    events: Vec<(E, EventStreamVersion)>,
+    version: Option<EventStreamVersion>,
+    position: Option<GlobalPosition>,
+    attempts: u32,
+    state: S,
+}
+
+impl<E, S> ExecuteOutcome<E, S> {
+    pub(crate) fn new(
+        events: Vec<(E, EventStreamVersion)>,
+        version: Option<EventStreamVersion>,
+        position: Option<GlobalPosition>,
+        attempts: u32,
+        state: S,
+    ) -> Self {
+        Self {
+            events,
+            version,
+            position,
+            attempts,
+            state,
+        }
+    }
+
+    /// The events the command emitted, each with the version it was appended at. Empty if the
+    /// command emitted none.
+    pub fn events(&self) -> &[(E, EventStreamVersion)] {
+        &self.events
+    }
+
+    pub fn into_events(self) -> Vec<(E, EventStreamVersion)> {
+        self.events
+    }
+
+    /// The version of the stream's last event once the command completed, or `None` if the
+    /// stream is still empty. Suitable for an ETag.
+    pub fn version(&self) -> Option<EventStreamVersion> {
+        self.version
+    }
+
+    /// The global position of the last event appended, or `None` if nothing was appended. Read
+    /// models whose checkpoint has reached it reflect the command's events.
+    pub fn position(&self) -> Option<GlobalPosition> {
+        self.position
+    }
+
+    /// How many times the command was handled, counting the attempt that succeeded. Zero if
+    /// the command was a repeat that wasn't handled again.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Whether events had already been appended under the command's idempotency key, in which
+    /// case the events, version and position are those of the execution that appended them and
+    /// the state is the aggregate's current state.
+    pub fn is_duplicate(&self) -> bool {
+        self.attempts == 0
+    }
+
+    /// The aggregate's state with the appended events applied.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn into_state(self) -> S {
+        self.state
+    }
+}
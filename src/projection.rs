@@ -0,0 +1,455 @@
+mod checkpoint;
+
+pub use checkpoint::{CheckpointStore, InMemoryCheckpointStore};
+
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::FutureExt;
+use tokio::sync::watch;
+
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_store::{EventStore, GlobalPosition};
+use crate::read_all::{EventFilter, ReadAllOptions};
+use crate::recorded_event::RecordedEvent;
+use crate::subscription::DEFAULT_POLL_INTERVAL;
+use crate::upcast::Upcasters;
+
+const DEFAULT_BATCH_SIZE: u64 = 100;
+
+/// A read model built from events across every stream in a store.
+pub trait Projection: Send {
+    type Event: Event;
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Identifies the projection's checkpoint, so it must stay the same across restarts.
+    fn name(&self) -> String;
+
+    /// Limits the events passed to `handle`. Every event read is deserialized as
+    /// `Self::Event`, so a projection over a store holding other event types needs a filter.
+    fn filter(&self) -> Option<EventFilter> {
+        None
+    }
+
+    fn handle(
+        &mut self,
+        event: &RecordedEvent<Self::Event>,
+    ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// How far a running projection has got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProjectionStatus {
+    position: Option<GlobalPosition>,
+    head: Option<GlobalPosition>,
+    caught_up: bool,
+}
+
+impl ProjectionStatus {
+    /// The position the projection has read up to: the last event it handled, or a later
+    /// event its filter skipped.
+    pub fn position(&self) -> Option<GlobalPosition> {
+        self.position
+    }
+
+    /// The position of the newest event in the store when the projection last checked.
+    pub fn head(&self) -> Option<GlobalPosition> {
+        self.head
+    }
+
+    /// Whether the projection's last read found no further events.
+    pub fn is_caught_up(&self) -> bool {
+        self.caught_up
+    }
+
+    /// How far the projection trails the newest event in the store, in commit positions. For
+    /// stores that number events sequentially this is the number of events left to read; for
+    /// Kurrent it is a distance in the transaction log. Events excluded by the projection's
+    /// filter count towards the lag until a batch reads past them.
+    pub fn lag(&self) -> u64 {
+        match (self.head, self.position) {
+            (None, _) => 0,
+            (Some(head), None) => head.commit() + 1,
+            (Some(head), Some(position)) => head.commit().saturating_sub(position.commit()),
+        }
+    }
+}
+
+/// Feeds events from a store's global log to a `Projection`, in batches.
+///
+/// The runner saves a checkpoint after each batch and resumes from it when started again, so
+/// events handled after the last checkpoint may be handed to the projection a second time
+/// following a crash. Once caught up, it checks for new events at the poll interval.
+pub struct ProjectionRunner<S, P, C> {
+    store: S,
+    projection: P,
+    checkpoints: C,
+    batch_size: u64,
+    poll_interval: Duration,
+    upcasters: Upcasters,
+    status: watch::Sender<ProjectionStatus>,
+}
+
+impl<S, P, C> ProjectionRunner<S, P, C>
+where
+    S: EventStore + Send + Sync,
+    P: Projection,
+    C: CheckpointStore + Send,
+{
+    pub fn new(store: S, projection: P, checkpoints: C) -> Self {
+        Self {
+            store,
+            projection,
+            checkpoints,
+            batch_size: DEFAULT_BATCH_SIZE,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            upcasters: Upcasters::default(),
+            status: watch::Sender::new(ProjectionStatus::default()),
+        }
+    }
+
+    /// Sets how many events are read and handled between checkpoints.
+    pub fn with_batch_size(mut self, batch_size: u64) -> Result<Self, Error> {
+        if batch_size == 0 {
+            return Err(Error::InvalidConfig {
+                message: "batch_size cannot be 0".to_string(),
+                parameter: Some("batch_size".to_string()),
+            });
+        }
+        self.batch_size = batch_size;
+        Ok(self)
+    }
+
+    /// Sets how often the runner checks for new events once caught up.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets the upcasters applied to events before they are handed to the projection.
+    pub fn with_upcasters(mut self, upcasters: Upcasters) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    /// Returns a receiver that is updated with the projection's status after every batch.
+    pub fn status(&self) -> watch::Receiver<ProjectionStatus> {
+        self.status.subscribe()
+    }
+
+    /// Runs the projection until reading, handling or checkpointing fails.
+    pub async fn run(self) -> Result<P, Error> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Runs the projection until `shutdown` resolves, then returns it. A batch in progress is
+    /// finished and checkpointed first.
+    pub async fn run_until(
+        mut self,
+        shutdown: impl std::future::Future<Output = ()>,
+    ) -> Result<P, Error> {
+        let name = self.projection.name();
+        let mut position = self.checkpoints.load(&name).await?;
+        let mut shutdown = pin!(shutdown);
+
+        loop {
+            let handled = self.run_batch(&name, &mut position).await?;
+            let caught_up = handled < self.batch_size;
+            let head = self.store.head_position().await?;
+            self.status.send_replace(ProjectionStatus {
+                position,
+                head,
+                caught_up,
+            });
+
+            if caught_up {
+                tokio::select! {
+                    _ = shutdown.as_mut() => break,
+                    _ = tokio::time::sleep(self.poll_interval) => {}
+                }
+            } else if shutdown.as_mut().now_or_never().is_some() {
+                break;
+            }
+        }
+
+        Ok(self.projection)
+    }
+
+    /// Handles the next batch of events after `position` and checkpoints how far it read,
+    /// returning how many events were handled.
+    async fn run_batch(
+        &mut self,
+        name: &str,
+        position: &mut Option<GlobalPosition>,
+    ) -> Result<u64, Error> {
+        let mut options = ReadAllOptions::new().max_count(self.batch_size);
+        if let Some(after) = *position {
+            options = options.after(after);
+        }
+        if let Some(filter) = self.projection.filter() {
+            options = options.filter(filter);
+        }
+
+        let mut events = self
+            .store
+            .read_all::<P::Event>(options)
+            .await?
+            .with_upcasters(self.upcasters.clone());
+        let mut last_handled = None;
+        let mut handled = 0;
+        let result = loop {
+            let recorded = match events.next_recorded().await {
+                Ok(Some(recorded)) => recorded,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            if let Err(e) = self.projection.handle(&recorded).await {
+                break Err(Error::ProjectionFailed {
+                    projection: name.to_string(),
+                    position: recorded.position(),
+                    source: Box::new(e),
+                });
+            }
+            last_handled = Some(recorded.position());
+            handled += 1;
+        };
+
+        // Keep the progress made before a failure, so it isn't handled again on restart. A batch
+        // read to its end also moves past the events the filter skipped after the last one
+        // handled, so they aren't read again by the next poll.
+        let reached = match result {
+            Ok(()) => events.read_through(),
+            Err(_) => last_handled,
+        };
+        if let Some(reached) = reached
+            && *position != Some(reached)
+        {
+            self.checkpoints.save(name, reached).await?;
+            *position = Some(reached);
+        }
+        result.map(|()| handled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::event_store::{EventStreamId, ExpectedVersion};
+    use crate::in_memory_adapter::InMemoryEventStore;
+
+    #[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+    enum TestEvent {
+        Happened { value: u32 },
+    }
+
+    impl Event for TestEvent {
+        fn event_type(&self) -> String {
+            "TestEvent.Happened".to_string()
+        }
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("refused to handle {0}")]
+    struct Refused(u32);
+
+    #[derive(Default)]
+    struct Sum {
+        values: Vec<u32>,
+        refuse: Option<u32>,
+        filter: Option<EventFilter>,
+    }
+
+    impl Projection for Sum {
+        type Event = TestEvent;
+        type Error = Refused;
+
+        fn name(&self) -> String {
+            "sum".to_string()
+        }
+
+        fn filter(&self) -> Option<EventFilter> {
+            self.filter.clone()
+        }
+
+        async fn handle(&mut self, event: &RecordedEvent<TestEvent>) -> Result<(), Refused> {
+            let TestEvent::Happened { value } = *event.event();
+            if self.refuse == Some(value) {
+                return Err(Refused(value));
+            }
+            self.values.push(value);
+            Ok(())
+        }
+    }
+
+    async fn publish_values(store: &mut InMemoryEventStore, values: impl IntoIterator<Item = u32>) {
+        for value in values {
+            store
+                .publish(
+                    EventStreamId::new(),
+                    vec![TestEvent::Happened { value }],
+                    ExpectedVersion::Any,
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Runs `projection` until it has caught up with the store.
+    async fn run_to_end(
+        store: &InMemoryEventStore,
+        projection: Sum,
+        checkpoints: &InMemoryCheckpointStore,
+    ) -> Result<Sum, Error> {
+        let runner = ProjectionRunner::new(store.clone(), projection, checkpoints.clone())
+            .with_batch_size(2)
+            .unwrap();
+        let mut status = runner.status();
+        let shutdown = async move {
+            let _ = status.wait_for(ProjectionStatus::is_caught_up).await;
+        };
+        runner.run_until(shutdown).await
+    }
+
+    #[tokio::test]
+    async fn handles_events_and_checkpoints_last_position() {
+        let mut store = InMemoryEventStore::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+        publish_values(&mut store, 0..5).await;
+
+        let projection = run_to_end(&store, Sum::default(), &checkpoints)
+            .await
+            .unwrap();
+
+        assert_eq!(projection.values, vec![0, 1, 2, 3, 4]);
+        assert_eq!(
+            checkpoints.load("sum").await.unwrap(),
+            Some(GlobalPosition::from_sequence(4))
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_from_checkpoint() {
+        let mut store = InMemoryEventStore::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+        publish_values(&mut store, 0..3).await;
+        run_to_end(&store, Sum::default(), &checkpoints)
+            .await
+            .unwrap();
+
+        publish_values(&mut store, 3..5).await;
+        let projection = run_to_end(&store, Sum::default(), &checkpoints)
+            .await
+            .unwrap();
+
+        assert_eq!(projection.values, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn checkpoints_progress_before_a_failed_event() {
+        let mut store = InMemoryEventStore::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+        publish_values(&mut store, 0..4).await;
+
+        let projection = Sum {
+            refuse: Some(3),
+            ..Default::default()
+        };
+        match run_to_end(&store, projection, &checkpoints).await {
+            Err(Error::ProjectionFailed {
+                projection,
+                position,
+                ..
+            }) => {
+                assert_eq!(projection, "sum");
+                assert_eq!(position, GlobalPosition::from_sequence(3));
+            }
+            Err(other) => panic!("Expected projection failure, got: {:?}", other),
+            Ok(_) => panic!("Expected projection failure, got a finished projection"),
+        }
+
+        assert_eq!(
+            checkpoints.load("sum").await.unwrap(),
+            Some(GlobalPosition::from_sequence(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn checkpoints_past_events_the_filter_skipped() {
+        let mut store = InMemoryEventStore::new();
+        let checkpoints = InMemoryCheckpointStore::new();
+        let stream_id = EventStreamId::new();
+        store
+            .publish(
+                stream_id.clone(),
+                vec![TestEvent::Happened { value: 0 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+        publish_values(&mut store, 1..4).await;
+
+        let projection = Sum {
+            filter: Some(EventFilter::stream_prefix([stream_id.to_string()])),
+            ..Default::default()
+        };
+        let projection = run_to_end(&store, projection, &checkpoints).await.unwrap();
+
+        assert_eq!(projection.values, vec![0]);
+        assert_eq!(
+            checkpoints.load("sum").await.unwrap(),
+            Some(GlobalPosition::from_sequence(3))
+        );
+    }
+
+    #[test]
+    fn lag_counts_positions_behind_head() {
+        let status = |position: Option<u64>, head: Option<u64>| ProjectionStatus {
+            position: position.map(GlobalPosition::from_sequence),
+            head: head.map(GlobalPosition::from_sequence),
+            caught_up: false,
+        };
+
+        assert_eq!(status(None, None).lag(), 0);
+        assert_eq!(status(None, Some(4)).lag(), 5);
+        assert_eq!(status(Some(1), Some(4)).lag(), 3);
+        assert_eq!(status(Some(4), Some(4)).lag(), 0);
+    }
+
+    #[tokio::test]
+    async fn follows_new_events_until_shut_down() {
+        let mut store = InMemoryEventStore::new();
+        publish_values(&mut store, 0..3).await;
+
+        let runner = ProjectionRunner::new(
+            store.clone(),
+            Sum::default(),
+            InMemoryCheckpointStore::new(),
+        )
+        .with_batch_size(1)
+        .unwrap()
+        .with_poll_interval(Duration::from_millis(10));
+        let mut status = runner.status();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(runner.run_until(async move {
+            let _ = stopped.await;
+        }));
+
+        publish_values(&mut store, [3]).await;
+        let caught_up = *status
+            .wait_for(|s| s.is_caught_up() && s.head() == Some(GlobalPosition::from_sequence(3)))
+            .await
+            .unwrap();
+        assert_eq!(caught_up.lag(), 0);
+
+        stop.send(()).unwrap();
+        let projection = tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .expect("projection did not shut down")
+            .unwrap()
+            .unwrap();
+        assert_eq!(projection.values, vec![0, 1, 2, 3]);
+    }
+}
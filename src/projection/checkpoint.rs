@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::error::Error;
+use crate::event_store::GlobalPosition;
+
+/// Remembers how far each projection has read, so a `ProjectionRunner` can resume where it
+/// left off.
+///
+/// `Sqlite` and `Postgres` store checkpoints in the same database as their events.
+pub trait CheckpointStore {
+    /// Returns the position of the last event `projection` handled, or `None` if it has not
+    /// saved a checkpoint yet.
+    fn load(
+        &self,
+        projection: &str,
+    ) -> impl std::future::Future<Output = Result<Option<GlobalPosition>, Error>> + Send;
+
+    fn save(
+        &mut self,
+        projection: &str,
+        position: GlobalPosition,
+    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+}
+
+/// A `CheckpointStore` that keeps checkpoints in process memory. Clones share the same
+/// checkpoints.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryCheckpointStore {
+    checkpoints: Arc<RwLock<HashMap<String, GlobalPosition>>>,
+}
+
+impl InMemoryCheckpointStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CheckpointStore for InMemoryCheckpointStore {
+    async fn load(&self, projection: &str) -> Result<Option<GlobalPosition>, Error> {
+        let checkpoints = self
+            .checkpoints
+            .read()
+            .expect("in-memory checkpoint store lock poisoned");
+        Ok(checkpoints.get(projection).copied())
+    }
+
+    async fn save(&mut self, projection: &str, position: GlobalPosition) -> Result<(), Error> {
+        let mut checkpoints = self
+            .checkpoints
+            .write()
+            .expect("in-memory checkpoint store lock poisoned");
+        checkpoints.insert(projection.to_string(), position);
+        Ok(())
+    }
+}
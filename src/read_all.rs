@@ -0,0 +1,106 @@
+use crate::event_store::{EventStreamId, GlobalPosition};
+
+/// Selects which events `read_all` and `subscribe_to_all` return from the global log.
+///
+/// By default every event is returned, starting from the beginning of the log.
+#[derive(Debug, Clone, Default)]
+pub struct ReadAllOptions {
+    pub(crate) after: Option<GlobalPosition>,
+    pub(crate) max_count: Option<u64>,
+    pub(crate) filter: Option<EventFilter>,
+}
+
+impl ReadAllOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts after `position`, typically the position of the last event a consumer handled.
+    pub fn after(mut self, position: GlobalPosition) -> Self {
+        self.after = Some(position);
+        self
+    }
+
+    /// Limits a read to at most `count` events. Subscriptions ignore this.
+    pub fn max_count(mut self, count: u64) -> Self {
+        self.max_count = Some(count);
+        self
+    }
+
+    /// Returns only events matching `filter`. Filtering happens in the store, so skipped events
+    /// are never deserialized.
+    pub fn filter(mut self, filter: EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+}
+
+/// Restricts a read of the global log to events whose type or stream id starts with one of a
+/// set of prefixes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventFilter {
+    EventTypePrefix(Vec<String>),
+    StreamPrefix(Vec<String>),
+}
+
+impl EventFilter {
+    pub fn event_type_prefix<I, S>(prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::EventTypePrefix(prefixes.into_iter().map(Into::into).collect())
+    }
+
+    pub fn stream_prefix<I, S>(prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::StreamPrefix(prefixes.into_iter().map(Into::into).collect())
+    }
+
+    pub(crate) fn prefixes(&self) -> &[String] {
+        match self {
+            Self::EventTypePrefix(prefixes) | Self::StreamPrefix(prefixes) => prefixes,
+        }
+    }
+
+    pub(crate) fn matches(&self, stream_id: &EventStreamId, event_type: &str) -> bool {
+        match self {
+            Self::EventTypePrefix(prefixes) => prefixes.iter().any(|p| event_type.starts_with(p)),
+            Self::StreamPrefix(prefixes) => {
+                let stream_id = stream_id.to_string();
+                prefixes.iter().any(|p| stream_id.starts_with(p))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_type_prefix_matches_on_event_type() {
+        let filter = EventFilter::event_type_prefix(["Account.", "Ledger."]);
+        let stream_id = EventStreamId::new();
+
+        assert!(filter.matches(&stream_id, "Account.Opened"));
+        assert!(filter.matches(&stream_id, "Ledger.Posted"));
+        assert!(!filter.matches(&stream_id, "Order.Placed"));
+    }
+
+    #[test]
+    fn stream_prefix_matches_on_stream_id() {
+        let stream_id = EventStreamId::new();
+        let prefix = stream_id.to_string()[..8].to_string();
+        let filter = EventFilter::stream_prefix([prefix]);
+
+        assert!(filter.matches(&stream_id, "Account.Opened"));
+        assert!(!filter.matches(
+            &EventStreamId::from_uuid(uuid::Uuid::nil()),
+            "Account.Opened"
+        ));
+    }
+}
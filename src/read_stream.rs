@@ -0,0 +1,94 @@
+use crate::event_store::EventStreamVersion;
+
+/// Selects which of a stream's events `read_stream_with` returns, and in which order.
+///
+/// By default every event is returned, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct ReadStreamOptions {
+    pub(crate) direction: ReadDirection,
+    pub(crate) position: Option<StreamPosition>,
+    pub(crate) max_count: Option<u64>,
+}
+
+impl ReadStreamOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads newest event first. Unless a position is set, the read starts at the stream's last
+    /// event.
+    pub fn backwards(self) -> Self {
+        self.direction(ReadDirection::Backwards)
+    }
+
+    pub fn direction(mut self, direction: ReadDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Starts the read at `position`, including the event there.
+    pub fn position(mut self, position: StreamPosition) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    /// Limits the read to at most `count` events.
+    pub fn max_count(mut self, count: u64) -> Self {
+        self.max_count = Some(count);
+        self
+    }
+
+    /// Where the read starts: the position set, or else the end it reads away from.
+    pub(crate) fn start(&self) -> StreamPosition {
+        self.position.unwrap_or(match self.direction {
+            ReadDirection::Forwards => StreamPosition::Start,
+            ReadDirection::Backwards => StreamPosition::End,
+        })
+    }
+
+    /// The version the read starts at, which is past any stream's last event for `End`.
+    pub(crate) fn start_version(&self) -> u64 {
+        match self.start() {
+            StreamPosition::Start => 0,
+            StreamPosition::End => i64::MAX as u64,
+            StreamPosition::Version(version) => version.value(),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.max_count.map_or(usize::MAX, |count| count as usize)
+    }
+}
+
+/// The order a stream is read in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadDirection {
+    /// Oldest event first.
+    #[default]
+    Forwards,
+    /// Newest event first.
+    Backwards,
+}
+
+/// A point in a stream to start a read at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamPosition {
+    /// The stream's first event.
+    Start,
+    /// The stream's last event. Reading forwards from here returns nothing.
+    End,
+    /// The event at this version.
+    Version(EventStreamVersion),
+}
+
+impl From<StreamPosition> for eventstore::StreamPosition<u64> {
+    fn from(position: StreamPosition) -> Self {
+        match position {
+            StreamPosition::Start => eventstore::StreamPosition::Start,
+            StreamPosition::End => eventstore::StreamPosition::End,
+            StreamPosition::Version(version) => {
+                eventstore::StreamPosition::Position(version.value())
+            }
+        }
+    }
+}
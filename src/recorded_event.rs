@@ -0,0 +1,62 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::event_store::{EventStreamId, EventStreamVersion, GlobalPosition};
+use crate::metadata::EventMetadata;
+
+/// An event read back from a store, along with everything recorded about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEvent<E> {
+    pub(crate) event: E,
+    pub(crate) id: Uuid,
+    pub(crate) event_type: String,
+    pub(crate) stream_id: EventStreamId,
+    pub(crate) version: EventStreamVersion,
+    pub(crate) position: GlobalPosition,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) metadata: EventMetadata,
+}
+
+impl<E> RecordedEvent<E> {
+    pub fn event(&self) -> &E {
+        &self.event
+    }
+
+    pub fn into_event(self) -> E {
+        self.event
+    }
+
+    /// The id the event was appended with.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The event type string, as returned by `Event::event_type` when it was appended.
+    pub fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    pub fn stream_id(&self) -> &EventStreamId {
+        &self.stream_id
+    }
+
+    /// The event's version within its stream.
+    pub fn version(&self) -> EventStreamVersion {
+        self.version
+    }
+
+    /// The event's position in the global log, which a consumer of `read_all` or
+    /// `subscribe_to_all` can store to resume from later.
+    pub fn position(&self) -> GlobalPosition {
+        self.position
+    }
+
+    /// When the store recorded the event.
+    pub fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+
+    pub fn metadata(&self) -> &EventMetadata {
+        &self.metadata
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::command::AsyncCommand;
+use crate::error::Error;
+use crate::event_store::{EventStreamId, EventStreamVersion};
+
+/// Aggregate state that can be snapshotted by `execute_with_snapshots`.
+pub trait SnapshotState: Serialize + DeserializeOwned {
+    /// Identifies the serialized form of the state. Change it whenever that form changes, so
+    /// snapshots written by older code are ignored instead of being misread.
+    const SCHEMA_VERSION: u32;
+}
+
+/// An aggregate's state as of a version of its stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    stream_id: EventStreamId,
+    version: EventStreamVersion,
+    schema_version: u32,
+    state: serde_json::Value,
+}
+
+impl Snapshot {
+    pub fn new(
+        stream_id: EventStreamId,
+        version: EventStreamVersion,
+        schema_version: u32,
+        state: serde_json::Value,
+    ) -> Self {
+        Self {
+            stream_id,
+            version,
+            schema_version,
+            state,
+        }
+    }
+
+    pub fn stream_id(&self) -> &EventStreamId {
+        &self.stream_id
+    }
+
+    /// The version of the last event applied to the state.
+    pub fn version(&self) -> EventStreamVersion {
+        self.version
+    }
+
+    /// The `SnapshotState::SCHEMA_VERSION` the state was written with.
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    pub fn state(&self) -> &serde_json::Value {
+        &self.state
+    }
+}
+
+/// Holds the latest snapshot of each stream.
+///
+/// `Sqlite` and `Postgres` store snapshots in the same database as their events.
+pub trait SnapshotStore {
+    fn load(
+        &self,
+        stream_id: &EventStreamId,
+    ) -> impl std::future::Future<Output = Result<Option<Snapshot>, Error>> + Send;
+
+    /// Saves `snapshot`, replacing any earlier snapshot of the same stream.
+    fn save(
+        &mut self,
+        snapshot: Snapshot,
+    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+}
+
+/// A `SnapshotStore` that keeps snapshots in process memory. Clones share the same snapshots.
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySnapshotStore {
+    snapshots: Arc<RwLock<HashMap<EventStreamId, Snapshot>>>,
+}
+
+impl InMemorySnapshotStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SnapshotStore for InMemorySnapshotStore {
+    async fn load(&self, stream_id: &EventStreamId) -> Result<Option<Snapshot>, Error> {
+        let snapshots = self
+            .snapshots
+            .read()
+            .expect("in-memory snapshot store lock poisoned");
+        Ok(snapshots.get(stream_id).cloned())
+    }
+
+    async fn save(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        let mut snapshots = self
+            .snapshots
+            .write()
+            .expect("in-memory snapshot store lock poisoned");
+        snapshots.insert(snapshot.stream_id.clone(), snapshot);
+        Ok(())
+    }
+}
+
+/// How `execute` restores and records a command's state, which lets the same retry loop run
+/// with or without snapshots.
+pub(crate) trait Snapshots<C: AsyncCommand> {
+    /// Restores the command's state from the latest usable snapshot, returning the version it
+    /// was taken at.
+    async fn restore(&self, command: &mut C) -> Result<Option<EventStreamVersion>, Error>;
+
+    /// Builds the snapshot to record if `events` are appended after `current`, or `None` if
+    /// one isn't due yet.
+    fn prepare(
+        &self,
+        command: &C,
+        events: &[C::Event],
+        restored: Option<EventStreamVersion>,
+        current: Option<EventStreamVersion>,
+    ) -> Option<Snapshot>;
+
+    async fn save(&mut self, snapshot: Snapshot);
+}
+
+pub(crate) struct NoSnapshots;
+
+impl<C: AsyncCommand> Snapshots<C> for NoSnapshots {
+    async fn restore(&self, _: &mut C) -> Result<Option<EventStreamVersion>, Error> {
+        Ok(None)
+    }
+
+    fn prepare(
+        &self,
+        _: &C,
+        _: &[C::Event],
+        _: Option<EventStreamVersion>,
+        _: Option<EventStreamVersion>,
+    ) -> Option<Snapshot> {
+        None
+    }
+
+    async fn save(&mut self, _: Snapshot) {}
+}
+
+pub(crate) struct Snapshotting<'a, T> {
+    pub(crate) store: &'a mut T,
+    pub(crate) frequency: u64,
+}
+
+impl<C, T> Snapshots<C> for Snapshotting<'_, T>
+where
+    C: AsyncCommand,
+    C::State: SnapshotState,
+    T: SnapshotStore,
+{
+    async fn restore(&self, command: &mut C) -> Result<Option<EventStreamVersion>, Error> {
+        let Some(snapshot) = self.store.load(&command.event_stream_id()).await? else {
+            return Ok(None);
+        };
+        if snapshot.schema_version != C::State::SCHEMA_VERSION {
+            return Ok(None);
+        }
+        // A snapshot that no longer deserializes is as stale as one with the wrong schema.
+        match serde_json::from_value::<C::State>(snapshot.state) {
+            Ok(state) => {
+                command.set_state(&state);
+                Ok(Some(snapshot.version))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn prepare(
+        &self,
+        command: &C,
+        events: &[C::Event],
+        restored: Option<EventStreamVersion>,
+        current: Option<EventStreamVersion>,
+    ) -> Option<Snapshot> {
+        let next_version = current.map_or(0, |v| v.value() + 1) + events.len() as u64;
+        let since_snapshot = next_version.saturating_sub(restored.map_or(0, |v| v.value() + 1));
+        if events.is_empty() || since_snapshot < self.frequency {
+            return None;
+        }
+
+        let mut command = command.clone();
+        for event in events {
+            command.apply(event);
+        }
+        let state = serde_json::to_value(command.get_state()).ok()?;
+        Some(Snapshot::new(
+            command.event_stream_id(),
+            EventStreamVersion::new(next_version - 1),
+            C::State::SCHEMA_VERSION,
+            state,
+        ))
+    }
+
+    async fn save(&mut self, snapshot: Snapshot) {
+        // The events are already committed by the time a snapshot is saved, so failing to save
+        // one must not fail the command; the next execution just replays a little more.
+        let _ = self.store.save(snapshot).await;
+    }
+}
@@ -0,0 +1,977 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use sqlx::QueryBuilder;
+use sqlx::sqlite::{
+    Sqlite as SqliteDb, SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool,
+    SqlitePoolOptions,
+};
+
+use crate::codec::{Codecs, EventCodec, Json};
+use crate::deletion::Deletion;
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_store::{
+    AppendResult, EventStore, EventStreamId, EventStreamVersion, ExpectedVersion, GlobalPosition,
+    replayed_append,
+};
+use crate::event_stream::{EventStream, StoredEvent, read_through};
+use crate::new_event::NewEvent;
+use crate::projection::CheckpointStore;
+use crate::read_all::{EventFilter, ReadAllOptions};
+use crate::read_stream::{ReadDirection, ReadStreamOptions, StreamPosition};
+use crate::snapshot::{Snapshot, SnapshotStore};
+use crate::stream_metadata::StreamMetadata;
+use crate::subscription::{
+    CATCH_UP_BATCH_SIZE, CatchUpSource, DEFAULT_POLL_INTERVAL, Subscription,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS mneme_events (
+        position INTEGER PRIMARY KEY AUTOINCREMENT,
+        event_id TEXT NOT NULL,
+        stream_id TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        event_type TEXT NOT NULL,
+        data BLOB NOT NULL,
+        metadata TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        UNIQUE (stream_id, version)
+    );
+
+    -- Finds the events an append replays, which are looked up by id.
+    CREATE INDEX IF NOT EXISTS mneme_events_event_id ON mneme_events (stream_id, event_id);
+
+    CREATE TABLE IF NOT EXISTS mneme_streams (
+        stream_id TEXT PRIMARY KEY,
+        last_version INTEGER,
+        truncate_before INTEGER NOT NULL,
+        deleted BOOLEAN NOT NULL,
+        tombstoned BOOLEAN NOT NULL,
+        metadata TEXT,
+        metadata_version INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS mneme_snapshots (
+        stream_id TEXT PRIMARY KEY,
+        version INTEGER NOT NULL,
+        schema_version INTEGER NOT NULL,
+        state TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS mneme_checkpoints (
+        projection TEXT PRIMARY KEY,
+        commit_position INTEGER NOT NULL,
+        prepare_position INTEGER NOT NULL
+    );
+";
+
+/// An `EventStore` backed by a single local SQLite database.
+///
+/// The schema is created on open if it does not already exist. Deleting or truncating a stream
+/// deletes its discarded events from the database. Clones share the same
+/// connection pool.
+#[derive(Clone)]
+pub struct Sqlite {
+    pool: SqlitePool,
+    poll_interval: Duration,
+    codec: Arc<dyn EventCodec>,
+    codecs: Codecs,
+}
+
+impl Sqlite {
+    /// Opens the database file at `path`, creating it if it does not exist.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        Self::connect_with(SqlitePoolOptions::new(), options).await
+    }
+
+    /// Opens a private database that lives only as long as this store and its clones.
+    pub async fn in_memory() -> Result<Self, Error> {
+        let options = SqliteConnectOptions::new().in_memory(true);
+        // Every connection to an in-memory database sees its own empty database, so the pool
+        // must hold on to exactly one.
+        let pool_options = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+        Self::connect_with(pool_options, options).await
+    }
+
+    async fn connect_with(
+        pool_options: SqlitePoolOptions,
+        options: SqliteConnectOptions,
+    ) -> Result<Self, Error> {
+        let pool = pool_options.connect_with(options).await?;
+        sqlx::raw_sql(SCHEMA).execute(&pool).await?;
+        Ok(Self {
+            pool,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            codec: Arc::new(Json),
+            codecs: Codecs::default(),
+        })
+    }
+
+    /// Sets how often subscriptions check for new events once caught up. Other processes may
+    /// write to the same database, so subscriptions poll rather than wait for a notification.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets the codec events are serialized with, unless their type names its own, and registers
+    /// it to read them back with.
+    pub fn with_codec(mut self, codec: impl EventCodec + 'static) -> Self {
+        self.codec = Arc::new(codec);
+        self.codecs = self.codecs.register_shared(self.codec.clone());
+        self
+    }
+
+    /// Sets the codecs events are read back with, chosen by the content type each was written
+    /// with. The codec the store writes with is always among them.
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs.register_shared(self.codec.clone());
+        self
+    }
+
+    async fn events_after(
+        &self,
+        stream_id: &EventStreamId,
+        after: Option<EventStreamVersion>,
+    ) -> Result<Vec<StoredEvent>, Error> {
+        let first = after.map_or(0, |v| v.value() + 1);
+        let options = ReadStreamOptions::new()
+            .position(StreamPosition::Version(EventStreamVersion::new(first)));
+        let mut tx = self.pool.begin().await?;
+        let events = read_events(&mut tx, stream_id, &options).await?;
+        tx.commit().await?;
+        Ok(events)
+    }
+
+    /// The events a read of the global log with `options` returns, and the position it read
+    /// through, skipped events included.
+    async fn all_events(
+        &self,
+        options: &ReadAllOptions,
+    ) -> Result<(Vec<StoredEvent>, Option<GlobalPosition>), Error> {
+        let mut query = QueryBuilder::<SqliteDb>::new(
+            "SELECT position, event_id, stream_id, version, event_type, data, metadata, created_at
+             FROM mneme_events
+             WHERE position > ",
+        );
+        query.push_bind(options.after.map_or(0, |p| p.commit() as i64));
+        if let Some(filter) = &options.filter {
+            let column = match filter {
+                EventFilter::EventTypePrefix(_) => "event_type",
+                EventFilter::StreamPrefix(_) => "stream_id",
+            };
+            // Compare prefixes exactly rather than with LIKE, which ignores case in SQLite.
+            query.push(" AND (FALSE");
+            for prefix in filter.prefixes() {
+                query.push(format!(" OR substr({column}, 1, length("));
+                query.push_bind(prefix.clone());
+                query.push(")) = ");
+                query.push_bind(prefix.clone());
+            }
+            query.push(")");
+        }
+        query.push(" ORDER BY position");
+        if let Some(count) = options.max_count {
+            query.push(" LIMIT ");
+            query.push_bind(count as i64);
+        }
+
+        // Both queries read from the transaction's one snapshot.
+        let mut tx = self.pool.begin().await?;
+        let rows: Vec<EventRow> = query.build_query_as().fetch_all(&mut *tx).await?;
+        let events = rows
+            .into_iter()
+            .map(stored_event)
+            .collect::<Result<Vec<_>, _>>()?;
+        let head: Option<i64> = sqlx::query_scalar("SELECT MAX(position) FROM mneme_events")
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        let head = head.map(|p| GlobalPosition::from_sequence(p as u64));
+        let end = read_through(&events, options, head);
+        Ok((events, end))
+    }
+}
+
+type EventRow = (
+    i64,
+    String,
+    String,
+    i64,
+    String,
+    Vec<u8>,
+    String,
+    DateTime<Utc>,
+);
+
+fn stored_event(
+    (position, id, stream_id, version, event_type, data, metadata, created): EventRow,
+) -> Result<StoredEvent, Error> {
+    let decode_error = |e| Error::EventStoreDatabase(sqlx::Error::Decode(Box::new(e)));
+    let id = uuid::Uuid::parse_str(&id).map_err(decode_error)?;
+    let stream_id = uuid::Uuid::parse_str(&stream_id).map_err(decode_error)?;
+    Ok(StoredEvent {
+        id,
+        stream_id: EventStreamId::from_uuid(stream_id),
+        version: EventStreamVersion::new(version as u64),
+        position: GlobalPosition::from_sequence(position as u64),
+        event_type,
+        created,
+        metadata: serde_json::from_str(&metadata).map_err(Error::EventDeserializationError)?,
+        data,
+    })
+}
+
+/// The result of the earlier append `events` replay, if they were already appended.
+async fn replayed<E>(
+    connection: &mut SqliteConnection,
+    stream_id: &EventStreamId,
+    events: &[NewEvent<E>],
+    expected_version: ExpectedVersion,
+) -> Result<Option<AppendResult>, Error> {
+    let Some(first) = events.first() else {
+        return Ok(None);
+    };
+
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(
+        "SELECT event_id, version, position FROM mneme_events
+         WHERE stream_id = ?1 AND version >= (
+             SELECT MIN(version) FROM mneme_events WHERE stream_id = ?1 AND event_id = ?2
+         )
+         ORDER BY version LIMIT ?3",
+    )
+    .bind(stream_id.to_string())
+    .bind(first.id().to_string())
+    .bind(events.len() as i64)
+    .fetch_all(&mut *connection)
+    .await?;
+
+    let decode_error = |e| Error::EventStoreDatabase(sqlx::Error::Decode(Box::new(e)));
+    let recorded = rows
+        .into_iter()
+        .map(|(id, version, position)| {
+            Ok((
+                uuid::Uuid::parse_str(&id).map_err(decode_error)?,
+                EventStreamVersion::new(version as u64),
+                GlobalPosition::from_sequence(position as u64),
+            ))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(replayed_append(
+        events.iter().map(NewEvent::id),
+        &recorded,
+        expected_version,
+    ))
+}
+
+impl EventStore for Sqlite {
+    async fn append<E: Event>(
+        &mut self,
+        stream_id: EventStreamId,
+        events: Vec<NewEvent<E>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<AppendResult, Error> {
+        // Take the write lock up front so the version check and the inserts are atomic.
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let (deletion, last) = stream_state(&mut tx, &stream_id).await?;
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let current = deletion.current(last);
+
+        if (expected_version == ExpectedVersion::Any || !expected_version.matches(current))
+            && let Some(result) = replayed(&mut tx, &stream_id, &events, expected_version).await?
+        {
+            return Ok(result);
+        }
+
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        // A recreated stream continues from the version it was deleted at.
+        let next_version = last.map_or(0, |v| v.value() + 1);
+        let created_at = Utc::now();
+
+        let mut result = AppendResult::new(None, None);
+        for (event, version) in events.iter().zip(next_version..) {
+            let (data, metadata) = event.encode(self.codec.as_ref())?;
+            let metadata = serde_json::to_string(&metadata)?;
+            let position: i64 = sqlx::query_scalar(
+                "INSERT INTO mneme_events
+                     (event_id, stream_id, version, event_type, data, metadata, created_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)
+                 RETURNING position",
+            )
+            .bind(event.id().to_string())
+            .bind(stream_id.to_string())
+            .bind(version as i64)
+            .bind(event.event().event_type())
+            .bind(data)
+            .bind(metadata)
+            .bind(created_at)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|source| match source {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    Error::version_mismatch(stream_id.clone(), expected_version, current)
+                }
+                e => Error::EventStoreDatabase(e),
+            })?;
+            result = AppendResult::new(
+                Some(EventStreamVersion::new(version)),
+                Some(GlobalPosition::from_sequence(position as u64)),
+            );
+        }
+
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn read_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+    ) -> Result<EventStream<E>, Error> {
+        self.read_stream_with(stream_id, ReadStreamOptions::new())
+            .await
+    }
+
+    async fn read_stream_with<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        options: ReadStreamOptions,
+    ) -> Result<EventStream<E>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let events = read_events(&mut tx, &stream_id, &options).await?;
+        if events.is_empty() {
+            let (deletion, last) = stream_state(&mut tx, &stream_id).await?;
+            deletion.ensure_not_tombstoned(&stream_id)?;
+            // A stream whose events were all truncated away still exists.
+            if deletion.current(last).is_none() {
+                return Err(Error::EventStoreStreamNotFound(stream_id));
+            }
+        }
+        tx.commit().await?;
+        Ok(EventStream::from_stored(events, self.codecs.clone()))
+    }
+
+    async fn read_stream_after<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        after: EventStreamVersion,
+    ) -> Result<EventStream<E>, Error> {
+        let events = self.events_after(&stream_id, Some(after)).await?;
+        if events.is_empty() {
+            let (deletion, _) = stream_state(&mut *self.pool.acquire().await?, &stream_id).await?;
+            deletion.ensure_not_tombstoned(&stream_id)?;
+        }
+        Ok(EventStream::from_stored(events, self.codecs.clone()))
+    }
+
+    async fn subscribe_to_stream<E: Event>(
+        &self,
+        stream_id: EventStreamId,
+        from: Option<EventStreamVersion>,
+    ) -> Result<Subscription<E>, Error> {
+        Ok(Subscription::from_catch_up(
+            StreamCatchUp {
+                store: self.clone(),
+                stream_id,
+                after: from,
+            },
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn read_all<E: Event>(&self, options: ReadAllOptions) -> Result<EventStream<E>, Error> {
+        let (events, end) = self.all_events(&options).await?;
+        Ok(EventStream::from_stored_all(
+            events,
+            end,
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn subscribe_to_all<E: Event>(
+        &self,
+        options: ReadAllOptions,
+    ) -> Result<Subscription<E>, Error> {
+        Ok(Subscription::from_catch_up(
+            AllCatchUp {
+                store: self.clone(),
+                options: options.max_count(CATCH_UP_BATCH_SIZE),
+            },
+            self.codecs.clone(),
+        ))
+    }
+
+    async fn head_position(&self) -> Result<Option<GlobalPosition>, Error> {
+        let position: Option<i64> = sqlx::query_scalar("SELECT MAX(position) FROM mneme_events")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(position.map(|p| GlobalPosition::from_sequence(p as u64)))
+    }
+
+    async fn delete_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let (mut deletion, last) = stream_state(&mut tx, &stream_id).await?;
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let current = deletion.current(last);
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        if let Some(last) = current {
+            deletion.delete(last);
+            save_deletion(&mut tx, &stream_id, deletion, Some(last)).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn tombstone_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let (mut deletion, last) = stream_state(&mut tx, &stream_id).await?;
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let current = deletion.current(last);
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        deletion.tombstoned = true;
+        save_deletion(&mut tx, &stream_id, deletion, last).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn truncate_stream(
+        &mut self,
+        stream_id: EventStreamId,
+        before: EventStreamVersion,
+    ) -> Result<(), Error> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let (mut deletion, last) = stream_state(&mut tx, &stream_id).await?;
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        deletion.truncate(before);
+        save_deletion(&mut tx, &stream_id, deletion, last).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn read_stream_metadata(
+        &self,
+        stream_id: EventStreamId,
+    ) -> Result<(StreamMetadata, Option<EventStreamVersion>), Error> {
+        let mut connection = self.pool.acquire().await?;
+
+        let (deletion, _) = stream_state(&mut connection, &stream_id).await?;
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let (metadata, version) = stream_metadata(&mut connection, &stream_id).await?;
+        Ok((metadata.with_truncation(&deletion), version))
+    }
+
+    async fn set_stream_metadata(
+        &mut self,
+        stream_id: EventStreamId,
+        metadata: StreamMetadata,
+        expected_version: ExpectedVersion,
+    ) -> Result<EventStreamVersion, Error> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let (mut deletion, last) = stream_state(&mut tx, &stream_id).await?;
+        deletion.ensure_not_tombstoned(&stream_id)?;
+        let (_, current) = stream_metadata(&mut tx, &stream_id).await?;
+        if !expected_version.matches(current) {
+            return Err(Error::version_mismatch(
+                stream_id,
+                expected_version,
+                current,
+            ));
+        }
+
+        let (metadata, truncate_before) = metadata.split_truncation();
+        if let Some(before) = truncate_before {
+            deletion.truncate(before);
+            save_deletion(&mut tx, &stream_id, deletion, last).await?;
+        }
+        let version = current.map_or(EventStreamVersion::new(0), |v| {
+            EventStreamVersion::new(v.value() + 1)
+        });
+        sqlx::query(
+            "INSERT INTO mneme_streams
+                 (stream_id, last_version, truncate_before, deleted, tombstoned,
+                  metadata, metadata_version)
+             VALUES (?, ?, 0, FALSE, FALSE, ?, ?)
+             ON CONFLICT (stream_id) DO UPDATE SET
+                 metadata = excluded.metadata,
+                 metadata_version = excluded.metadata_version",
+        )
+        .bind(stream_id.to_string())
+        .bind(last.map(|v| v.value() as i64))
+        .bind(serde_json::to_string(&metadata)?)
+        .bind(version.value() as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(version)
+    }
+}
+
+/// The stream's metadata, without its truncation, and the version it was last written at.
+async fn stream_metadata(
+    connection: &mut SqliteConnection,
+    stream_id: &EventStreamId,
+) -> Result<(StreamMetadata, Option<EventStreamVersion>), Error> {
+    let row: Option<(Option<String>, Option<i64>)> =
+        sqlx::query_as("SELECT metadata, metadata_version FROM mneme_streams WHERE stream_id = ?")
+            .bind(stream_id.to_string())
+            .fetch_optional(&mut *connection)
+            .await?;
+    match row {
+        Some((Some(metadata), Some(version))) => Ok((
+            serde_json::from_str(&metadata)?,
+            Some(EventStreamVersion::new(version as u64)),
+        )),
+        _ => Ok((StreamMetadata::default(), None)),
+    }
+}
+
+/// The stream's events that `options` selects and its metadata still keeps. Truncated and deleted
+/// events are gone from the table already. Callers run it in a transaction, which reads the
+/// metadata and the events from one snapshot.
+async fn read_events(
+    connection: &mut SqliteConnection,
+    stream_id: &EventStreamId,
+    options: &ReadStreamOptions,
+) -> Result<Vec<StoredEvent>, Error> {
+    let (metadata, _) = stream_metadata(connection, stream_id).await?;
+    let last: Option<i64> = match metadata.max_count() {
+        Some(_) => {
+            sqlx::query_scalar("SELECT MAX(version) FROM mneme_events WHERE stream_id = ?")
+                .bind(stream_id.to_string())
+                .fetch_one(&mut *connection)
+                .await?
+        }
+        None => None,
+    };
+    let retention = metadata.retention(last.map(|v| EventStreamVersion::new(v as u64)));
+
+    let mut query = QueryBuilder::<SqliteDb>::new(
+        "SELECT position, event_id, stream_id, version, event_type, data, metadata, created_at
+         FROM mneme_events
+         WHERE stream_id = ",
+    );
+    query.push_bind(stream_id.to_string());
+    query.push(" AND version >= ");
+    query.push_bind(retention.first_version as i64);
+    if let Some(created_after) = retention.created_after {
+        // Timestamps are stored as text, whose offsets need not compare in order.
+        query.push(" AND julianday(created_at) > julianday(");
+        query.push_bind(created_after);
+        query.push(")");
+    }
+    let start = options.start_version().min(i64::MAX as u64) as i64;
+    match options.direction {
+        ReadDirection::Forwards => {
+            query.push(" AND version >= ");
+            query.push_bind(start);
+            query.push(" ORDER BY version");
+        }
+        ReadDirection::Backwards => {
+            query.push(" AND version <= ");
+            query.push_bind(start);
+            query.push(" ORDER BY version DESC");
+        }
+    }
+    if let Some(count) = options.max_count {
+        query.push(" LIMIT ");
+        query.push_bind(count.min(i64::MAX as u64) as i64);
+    }
+
+    let rows: Vec<EventRow> = query.build_query_as().fetch_all(&mut *connection).await?;
+    rows.into_iter().map(stored_event).collect()
+}
+
+/// The stream's deletion and the version of the last event ever appended to it, which outlives
+/// the event itself if it was deleted.
+async fn stream_state(
+    connection: &mut SqliteConnection,
+    stream_id: &EventStreamId,
+) -> Result<(Deletion, Option<EventStreamVersion>), Error> {
+    let row: Option<(Option<i64>, i64, bool, bool)> = sqlx::query_as(
+        "SELECT last_version, truncate_before, deleted, tombstoned
+         FROM mneme_streams WHERE stream_id = ?",
+    )
+    .bind(stream_id.to_string())
+    .fetch_optional(&mut *connection)
+    .await?;
+    let last: Option<i64> =
+        sqlx::query_scalar("SELECT MAX(version) FROM mneme_events WHERE stream_id = ?")
+            .bind(stream_id.to_string())
+            .fetch_one(&mut *connection)
+            .await?;
+
+    let (deletion, deleted_last) = row.map_or_else(Default::default, |row| {
+        let (last_version, truncate_before, deleted, tombstoned) = row;
+        let deletion = Deletion {
+            truncate_before: truncate_before as u64,
+            deleted,
+            tombstoned,
+        };
+        (deletion, last_version)
+    });
+    let last = last.or(deleted_last);
+    Ok((deletion, last.map(|v| EventStreamVersion::new(v as u64))))
+}
+
+/// Records `deletion` for the stream and deletes the events it discards.
+async fn save_deletion(
+    connection: &mut SqliteConnection,
+    stream_id: &EventStreamId,
+    deletion: Deletion,
+    last: Option<EventStreamVersion>,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO mneme_streams
+             (stream_id, last_version, truncate_before, deleted, tombstoned)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (stream_id) DO UPDATE SET
+             last_version = excluded.last_version,
+             truncate_before = excluded.truncate_before,
+             deleted = excluded.deleted,
+             tombstoned = excluded.tombstoned",
+    )
+    .bind(stream_id.to_string())
+    .bind(last.map(|v| v.value() as i64))
+    .bind(deletion.truncate_before as i64)
+    .bind(deletion.deleted)
+    .bind(deletion.tombstoned)
+    .execute(&mut *connection)
+    .await?;
+
+    sqlx::query("DELETE FROM mneme_events WHERE stream_id = ? AND (version < ? OR ?)")
+        .bind(stream_id.to_string())
+        .bind(deletion.truncate_before as i64)
+        .bind(deletion.tombstoned)
+        .execute(&mut *connection)
+        .await?;
+    Ok(())
+}
+
+impl SnapshotStore for Sqlite {
+    async fn load(&self, stream_id: &EventStreamId) -> Result<Option<Snapshot>, Error> {
+        let row: Option<(i64, i64, String)> = sqlx::query_as(
+            "SELECT version, schema_version, state FROM mneme_snapshots WHERE stream_id = ?",
+        )
+        .bind(stream_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|(version, schema_version, state)| {
+            Ok(Snapshot::new(
+                stream_id.clone(),
+                EventStreamVersion::new(version as u64),
+                schema_version as u32,
+                serde_json::from_str(&state)?,
+            ))
+        })
+        .transpose()
+    }
+
+    async fn save(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO mneme_snapshots (stream_id, version, schema_version, state)
+             VALUES (?, ?, ?, ?)
+             ON CONFLICT (stream_id) DO UPDATE SET
+                 version = excluded.version,
+                 schema_version = excluded.schema_version,
+                 state = excluded.state",
+        )
+        .bind(snapshot.stream_id().to_string())
+        .bind(snapshot.version().value() as i64)
+        .bind(snapshot.schema_version() as i64)
+        .bind(snapshot.state().to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+impl CheckpointStore for Sqlite {
+    async fn load(&self, projection: &str) -> Result<Option<GlobalPosition>, Error> {
+        let row: Option<(i64, i64)> = sqlx::query_as(
+            "SELECT commit_position, prepare_position FROM mneme_checkpoints WHERE projection = ?",
+        )
+        .bind(projection)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(commit, prepare)| GlobalPosition::new(commit as u64, prepare as u64)))
+    }
+
+    async fn save(&mut self, projection: &str, position: GlobalPosition) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO mneme_checkpoints (projection, commit_position, prepare_position)
+             VALUES (?, ?, ?)
+             ON CONFLICT (projection) DO UPDATE SET
+                 commit_position = excluded.commit_position,
+                 prepare_position = excluded.prepare_position",
+        )
+        .bind(projection)
+        .bind(position.commit() as i64)
+        .bind(position.prepare() as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+struct StreamCatchUp {
+    store: Sqlite,
+    stream_id: EventStreamId,
+    after: Option<EventStreamVersion>,
+}
+
+impl CatchUpSource for StreamCatchUp {
+    fn fetch(&mut self) -> BoxFuture<'_, Result<Vec<StoredEvent>, Error>> {
+        Box::pin(async move {
+            let events = self.store.events_after(&self.stream_id, self.after).await?;
+            if let Some(last) = events.last() {
+                self.after = Some(last.version);
+            }
+            Ok(events)
+        })
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(tokio::time::sleep(self.store.poll_interval))
+    }
+}
+
+struct AllCatchUp {
+    store: Sqlite,
+    options: ReadAllOptions,
+}
+
+impl CatchUpSource for AllCatchUp {
+    fn fetch(&mut self) -> BoxFuture<'_, Result<Vec<StoredEvent>, Error>> {
+        Box::pin(async move {
+            let (events, end) = self.store.all_events(&self.options).await?;
+            self.options.after = end.or(self.options.after);
+            Ok(events)
+        })
+    }
+
+    fn wait(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(tokio::time::sleep(self.store.poll_interval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_event::TestEvent;
+
+    async fn read_all_values(store: &Sqlite, options: ReadAllOptions) -> Vec<u32> {
+        let mut stream = store.read_all::<TestEvent>(options).await.unwrap();
+        let mut values = vec![];
+        while let Some((TestEvent::Happened { value }, _)) = stream.next().await.unwrap() {
+            values.push(value);
+        }
+        values
+    }
+
+    #[tokio::test]
+    async fn concurrent_appends_at_same_version_conflict() {
+        let path = std::env::temp_dir().join(format!("mneme-{}.db", uuid::Uuid::new_v4()));
+        let store = Sqlite::open(&path).await.unwrap();
+        let stream_id = EventStreamId::new();
+
+        store
+            .clone()
+            .publish(
+                stream_id.clone(),
+                vec![TestEvent::Happened { value: 1 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+
+        let appends = (0..4).map(|value| {
+            let mut store = store.clone();
+            let stream_id = stream_id.clone();
+            tokio::spawn(async move {
+                store
+                    .publish(
+                        stream_id,
+                        vec![TestEvent::Happened { value }],
+                        ExpectedVersion::Exact(EventStreamVersion::new(0)),
+                    )
+                    .await
+            })
+        });
+        let results = futures::future::join_all(appends).await;
+
+        let succeeded = results.iter().filter(|r| matches!(r, Ok(Ok(_)))).count();
+        let conflicted = results
+            .iter()
+            .filter(|r| matches!(r, Ok(Err(Error::EventStoreVersionMismatch { .. }))))
+            .count();
+        assert_eq!((succeeded, conflicted), (1, 3));
+        assert_eq!(
+            read_all_values(&store, ReadAllOptions::new()).await.len(),
+            2
+        );
+
+        store.pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn events_persist_across_reopen() {
+        let path = std::env::temp_dir().join(format!("mneme-{}.db", uuid::Uuid::new_v4()));
+        let stream_id = EventStreamId::new();
+
+        let mut store = Sqlite::open(&path).await.unwrap();
+        store
+            .publish(
+                stream_id.clone(),
+                vec![TestEvent::Happened { value: 1 }],
+                ExpectedVersion::Any,
+            )
+            .await
+            .unwrap();
+        store.pool.close().await;
+
+        let store = Sqlite::open(&path).await.unwrap();
+        assert_eq!(
+            read_all_values(&store, ReadAllOptions::new()).await,
+            vec![1]
+        );
+
+        store.pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn read_all_prefix_filters_are_case_sensitive() {
+        let mut store = Sqlite::in_memory().await.unwrap();
+
+        for value in 0..3 {
+            store
+                .publish(
+                    EventStreamId::new(),
+                    vec![TestEvent::Happened { value }],
+                    ExpectedVersion::Any,
+                )
+                .await
+                .unwrap();
+        }
+
+        let by_type = |prefix: &str| {
+            ReadAllOptions::new().filter(EventFilter::event_type_prefix([prefix.to_string()]))
+        };
+        assert_eq!(
+            read_all_values(&store, by_type("TestEvent.")).await,
+            vec![0, 1, 2]
+        );
+        assert!(
+            read_all_values(&store, by_type("testevent."))
+                .await
+                .is_empty()
+        );
+        assert_eq!(
+            read_all_values(&store, by_type("TestEvent.").max_count(2)).await,
+            vec![0, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn saves_and_overwrites_checkpoints() {
+        let mut store = Sqlite::in_memory().await.unwrap();
+
+        assert_eq!(
+            CheckpointStore::load(&store, "projection").await.unwrap(),
+            None
+        );
+        for sequence in [3, 7] {
+            CheckpointStore::save(
+                &mut store,
+                "projection",
+                GlobalPosition::from_sequence(sequence),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            CheckpointStore::load(&store, "projection").await.unwrap(),
+            Some(GlobalPosition::from_sequence(7))
+        );
+        assert_eq!(CheckpointStore::load(&store, "other").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn replaces_snapshots() {
+        let mut store = Sqlite::in_memory().await.unwrap();
+        let stream_id = EventStreamId::new();
+
+        assert_eq!(SnapshotStore::load(&store, &stream_id).await.unwrap(), None);
+        for version in [3, 7] {
+            SnapshotStore::save(
+                &mut store,
+                Snapshot::new(
+                    stream_id.clone(),
+                    EventStreamVersion::new(version),
+                    1,
+                    serde_json::json!({ "total": version }),
+                ),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            SnapshotStore::load(&store, &stream_id).await.unwrap(),
+            Some(Snapshot::new(
+                stream_id,
+                EventStreamVersion::new(7),
+                1,
+                serde_json::json!({ "total": 7 }),
+            ))
+        );
+    }
+}
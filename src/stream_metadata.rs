@@ -0,0 +1,362 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::deletion::Deletion;
+use crate::error::Error;
+use crate::event_store::EventStreamVersion;
+use crate::event_stream::StoredEvent;
+
+/// Settings that govern a stream as a whole rather than any one of its events.
+///
+/// Keys are stored under the names Kurrent uses in a stream's `$$` metadata stream, so metadata
+/// written through `Kurrent` is understood by the server itself. The other adapters enforce
+/// `max_count`, `max_age` and `truncate_before` on stream reads, and keep the ACL and cache
+/// control only to hand them back. Any other keys are user properties.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamMetadata {
+    #[serde(rename = "$maxAge", default, skip_serializing_if = "Option::is_none")]
+    #[serde(with = "seconds")]
+    max_age: Option<Duration>,
+    #[serde(rename = "$maxCount", default, skip_serializing_if = "Option::is_none")]
+    max_count: Option<u64>,
+    #[serde(rename = "$tb", default, skip_serializing_if = "Option::is_none")]
+    truncate_before: Option<u64>,
+    #[serde(
+        rename = "$cacheControl",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[serde(with = "seconds")]
+    cache_control: Option<Duration>,
+    #[serde(rename = "$acl", default, skip_serializing_if = "Option::is_none")]
+    acl: Option<StreamAcl>,
+    #[serde(flatten)]
+    values: serde_json::Map<String, serde_json::Value>,
+}
+
+impl StreamMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops reading events once they are older than `max_age`.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Reads only the stream's last `max_count` events.
+    pub fn with_max_count(mut self, max_count: u64) -> Self {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    /// Stops reading the events before `version`, as `EventStore::truncate_stream` does. Only
+    /// `Kurrent` can bring truncated events back by lowering it again.
+    pub fn with_truncate_before(mut self, version: EventStreamVersion) -> Self {
+        self.truncate_before = Some(version.value());
+        self
+    }
+
+    /// How long HTTP clients may cache the head of the stream.
+    pub fn with_cache_control(mut self, cache_control: Duration) -> Self {
+        self.cache_control = Some(cache_control);
+        self
+    }
+
+    pub fn with_acl(mut self, acl: StreamAcl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Adds a user property. Keys starting with `$` are reserved for settings Kurrent
+    /// understands, so they are rejected.
+    pub fn with_value(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Result<Self, Error> {
+        let key = key.into();
+        if key.starts_with('$') {
+            return Err(Error::InvalidConfig {
+                message: format!("stream metadata key '{key}' is reserved"),
+                parameter: Some(key),
+            });
+        }
+        self.values.insert(key, value.into());
+        Ok(self)
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    pub fn max_count(&self) -> Option<u64> {
+        self.max_count
+    }
+
+    pub fn truncate_before(&self) -> Option<EventStreamVersion> {
+        self.truncate_before.map(EventStreamVersion::new)
+    }
+
+    pub fn cache_control(&self) -> Option<Duration> {
+        self.cache_control
+    }
+
+    pub fn acl(&self) -> Option<&StreamAcl> {
+        self.acl.as_ref()
+    }
+
+    pub fn value(&self, key: &str) -> Option<&serde_json::Value> {
+        self.values.get(key)
+    }
+
+    /// The metadata as an adapter that records truncation in a `Deletion` keeps it: without the
+    /// truncation, which is returned alongside.
+    pub(crate) fn split_truncation(mut self) -> (Self, Option<EventStreamVersion>) {
+        let truncate_before = self.truncate_before();
+        self.truncate_before = None;
+        (self, truncate_before)
+    }
+
+    /// The metadata with the truncation `deletion` records, as `split_truncation` left it.
+    pub(crate) fn with_truncation(mut self, deletion: &Deletion) -> Self {
+        self.truncate_before = (deletion.truncate_before > 0).then_some(deletion.truncate_before);
+        self
+    }
+
+    /// What `max_count` and `max_age` keep of a stream whose last event is at `last`.
+    pub(crate) fn retention(&self, last: Option<EventStreamVersion>) -> Retention {
+        let first_version = match (self.max_count, last) {
+            (Some(count), Some(last)) => (last.value() + 1).saturating_sub(count),
+            _ => 0,
+        };
+        let created_after = self.max_age.and_then(|max_age| {
+            Utc::now().checked_sub_signed(chrono::Duration::from_std(max_age).ok()?)
+        });
+        Retention {
+            first_version,
+            created_after,
+        }
+    }
+}
+
+/// The events of a stream its `max_count` and `max_age` still keep.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Retention {
+    /// The oldest version kept.
+    pub(crate) first_version: u64,
+    /// Events created at or before this are no longer kept. Events from a clock ahead of ours
+    /// count as new.
+    pub(crate) created_after: Option<DateTime<Utc>>,
+}
+
+impl Retention {
+    pub(crate) fn keeps(&self, event: &StoredEvent) -> bool {
+        event.version.value() >= self.first_version
+            && self
+                .created_after
+                .is_none_or(|created_after| event.created > created_after)
+    }
+}
+
+/// The roles allowed to read, write and delete a stream and to read and write its metadata.
+/// Kurrent's default ACLs read back as no ACL.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamAcl {
+    #[serde(rename = "$r", default, skip_serializing_if = "Vec::is_empty")]
+    read_roles: Vec<String>,
+    #[serde(rename = "$w", default, skip_serializing_if = "Vec::is_empty")]
+    write_roles: Vec<String>,
+    #[serde(rename = "$d", default, skip_serializing_if = "Vec::is_empty")]
+    delete_roles: Vec<String>,
+    #[serde(rename = "$mr", default, skip_serializing_if = "Vec::is_empty")]
+    meta_read_roles: Vec<String>,
+    #[serde(rename = "$mw", default, skip_serializing_if = "Vec::is_empty")]
+    meta_write_roles: Vec<String>,
+}
+
+impl StreamAcl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_read_role(mut self, role: impl Into<String>) -> Self {
+        self.read_roles.push(role.into());
+        self
+    }
+
+    pub fn with_write_role(mut self, role: impl Into<String>) -> Self {
+        self.write_roles.push(role.into());
+        self
+    }
+
+    pub fn with_delete_role(mut self, role: impl Into<String>) -> Self {
+        self.delete_roles.push(role.into());
+        self
+    }
+
+    pub fn with_meta_read_role(mut self, role: impl Into<String>) -> Self {
+        self.meta_read_roles.push(role.into());
+        self
+    }
+
+    pub fn with_meta_write_role(mut self, role: impl Into<String>) -> Self {
+        self.meta_write_roles.push(role.into());
+        self
+    }
+
+    pub fn read_roles(&self) -> &[String] {
+        &self.read_roles
+    }
+
+    pub fn write_roles(&self) -> &[String] {
+        &self.write_roles
+    }
+
+    pub fn delete_roles(&self) -> &[String] {
+        &self.delete_roles
+    }
+
+    pub fn meta_read_roles(&self) -> &[String] {
+        &self.meta_read_roles
+    }
+
+    pub fn meta_write_roles(&self) -> &[String] {
+        &self.meta_write_roles
+    }
+}
+
+impl From<StreamMetadata> for eventstore::StreamMetadata {
+    fn from(metadata: StreamMetadata) -> Self {
+        eventstore::StreamMetadata {
+            max_count: metadata.max_count,
+            max_age: metadata.max_age,
+            truncate_before: metadata.truncate_before,
+            cache_control: metadata.cache_control,
+            acl: metadata.acl.map(|acl| {
+                let roles = |roles: Vec<String>| (!roles.is_empty()).then_some(roles);
+                eventstore::Acl::Stream(eventstore::StreamAcl {
+                    read_roles: roles(acl.read_roles),
+                    write_roles: roles(acl.write_roles),
+                    delete_roles: roles(acl.delete_roles),
+                    meta_read_roles: roles(acl.meta_read_roles),
+                    meta_write_roles: roles(acl.meta_write_roles),
+                })
+            }),
+            custom_properties: metadata.values.into_iter().collect(),
+        }
+    }
+}
+
+impl From<eventstore::StreamMetadata> for StreamMetadata {
+    fn from(metadata: eventstore::StreamMetadata) -> Self {
+        Self {
+            max_age: metadata.max_age,
+            max_count: metadata.max_count,
+            truncate_before: metadata.truncate_before,
+            cache_control: metadata.cache_control,
+            acl: match metadata.acl {
+                Some(eventstore::Acl::Stream(acl)) => Some(StreamAcl {
+                    read_roles: acl.read_roles.unwrap_or_default(),
+                    write_roles: acl.write_roles.unwrap_or_default(),
+                    delete_roles: acl.delete_roles.unwrap_or_default(),
+                    meta_read_roles: acl.meta_read_roles.unwrap_or_default(),
+                    meta_write_roles: acl.meta_write_roles.unwrap_or_default(),
+                }),
+                _ => None,
+            },
+            values: metadata.custom_properties.into_iter().collect(),
+        }
+    }
+}
+
+/// Serializes durations as whole seconds, as Kurrent does.
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::codec::Json;
+    use crate::event_store::{EventStreamId, GlobalPosition};
+    use crate::new_event::NewEvent;
+
+    fn stored(version: u64, created: DateTime<Utc>) -> StoredEvent {
+        StoredEvent::encode(
+            EventStreamId::new(),
+            EventStreamVersion::new(version),
+            GlobalPosition::from_sequence(version),
+            created,
+            &NewEvent::new(()),
+            &Json,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn keeps_only_the_events_within_max_count_and_max_age() {
+        let now = Utc::now();
+        let events = [
+            stored(0, now - chrono::Duration::hours(3)),
+            stored(1, now - chrono::Duration::hours(2)),
+            stored(2, now),
+            stored(3, now + chrono::Duration::minutes(1)),
+        ];
+        let last = Some(EventStreamVersion::new(3));
+        let kept = |metadata: StreamMetadata| {
+            let retention = metadata.retention(last);
+            events
+                .iter()
+                .filter(|event| retention.keeps(event))
+                .map(|event| event.version.value())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kept(StreamMetadata::new().with_max_count(2)), [2, 3]);
+        assert_eq!(
+            kept(StreamMetadata::new().with_max_age(Duration::from_secs(150 * 60))),
+            [1, 2, 3]
+        );
+        assert_eq!(kept(StreamMetadata::new().with_max_count(10)), [0, 1, 2, 3]);
+        assert_eq!(kept(StreamMetadata::new()), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_reserved_keys() {
+        assert!(matches!(
+            StreamMetadata::new().with_value("$maxCount", 1),
+            Err(Error::InvalidConfig { .. })
+        ));
+        assert_eq!(
+            StreamMetadata::new()
+                .with_value("owner", "billing")
+                .unwrap()
+                .value("owner"),
+            Some(&serde_json::Value::from("billing"))
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+use crate::codec::Codecs;
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_store::EventStreamVersion;
+use crate::event_stream::{StoredEvent, decode_resolved};
+use crate::recorded_event::RecordedEvent;
+use crate::upcast::Upcasters;
+
+/// How often adapters that cannot be notified of appends check for new events.
+pub(crate) const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The most events a subscription to all streams fetches at once while catching up.
+pub(crate) const CATCH_UP_BATCH_SIZE: u64 = 500;
+
+/// A live feed of events that first catches up on history and then waits for new events as
+/// they are appended.
+pub struct Subscription<E: Event> {
+    source: SubscriptionSource,
+    codecs: Codecs,
+    upcasters: Upcasters,
+    type_marker: PhantomData<E>,
+}
+
+enum SubscriptionSource {
+    Kurrent(Box<eventstore::Subscription>),
+    CatchUp {
+        source: Box<dyn CatchUpSource>,
+        pending: VecDeque<StoredEvent>,
+    },
+}
+
+/// Where the in-process adapters' subscriptions get their events from.
+pub(crate) trait CatchUpSource: Send {
+    /// Returns the events following those already returned, or nothing if there are none yet.
+    fn fetch(&mut self) -> BoxFuture<'_, Result<Vec<StoredEvent>, Error>>;
+
+    /// Resolves once new events may have been appended since the last fetch.
+    fn wait(&mut self) -> BoxFuture<'_, ()>;
+}
+
+impl<E: Event> Subscription<E> {
+    pub(crate) fn from_kurrent(subscription: eventstore::Subscription, codecs: Codecs) -> Self {
+        Self {
+            source: SubscriptionSource::Kurrent(Box::new(subscription)),
+            codecs,
+            upcasters: Upcasters::default(),
+            type_marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn from_catch_up(source: impl CatchUpSource + 'static, codecs: Codecs) -> Self {
+        Self {
+            source: SubscriptionSource::CatchUp {
+                source: Box::new(source),
+                pending: VecDeque::new(),
+            },
+            codecs,
+            upcasters: Upcasters::default(),
+            type_marker: PhantomData,
+        }
+    }
+
+    /// Upcasts events recorded at older schema versions with `upcasters` before
+    /// deserializing them.
+    pub fn with_upcasters(mut self, upcasters: Upcasters) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    /// Waits for the next event. Historical events are returned immediately; once caught up,
+    /// this waits until another event is appended.
+    pub async fn next(&mut self) -> Result<(E, EventStreamVersion), Error> {
+        let recorded = self.next_recorded().await?;
+        let version = recorded.version();
+        Ok((recorded.into_event(), version))
+    }
+
+    /// Waits for the next event, returning it along with its stream and global position.
+    pub async fn next_recorded(&mut self) -> Result<RecordedEvent<E>, Error> {
+        match &mut self.source {
+            SubscriptionSource::Kurrent(subscription) => loop {
+                let resolved = subscription.next().await?;
+                if let Some(recorded) = decode_resolved(&resolved, &self.codecs, &self.upcasters)? {
+                    return Ok(recorded);
+                }
+            },
+            SubscriptionSource::CatchUp { source, pending } => loop {
+                if let Some(stored) = pending.pop_front() {
+                    return stored.decode(&self.codecs, &self.upcasters);
+                }
+                let fetched = source.fetch().await?;
+                if fetched.is_empty() {
+                    source.wait().await;
+                }
+                pending.extend(fetched);
+            },
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+use crate::event::Event;
+
+/// The event the adapters' own tests write. Behaviour every adapter shares is tested in
+/// `tests/test_cases.rs` instead.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub(crate) enum TestEvent {
+    Happened { value: u32 },
+}
+
+impl Event for TestEvent {
+    fn event_type(&self) -> String {
+        "TestEvent.Happened".to_string()
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::codec::Codecs;
+use crate::error::Error;
+use crate::event::Event;
+use crate::metadata::EventMetadata;
+
+/// The schema version of events recorded before they carried one.
+pub(crate) const INITIAL_SCHEMA_VERSION: u32 = 1;
+
+type Upcaster = Arc<dyn Fn(Value) -> Value + Send + Sync>;
+
+/// Upgrades stored event payloads written by older code to the shape their `Event` type
+/// deserializes today.
+///
+/// Each upcaster is registered for an event type and the schema version it upgrades from, and
+/// returns the payload as of the next version. Upcasters for successive versions are chained,
+/// so a payload recorded at version 1 passes through the 1 → 2 upcaster, then the 2 → 3 one,
+/// and so on. Events recorded before schema versions were stored count as version 1.
+#[derive(Clone, Default)]
+pub struct Upcasters {
+    upcasters: HashMap<(String, u32), Upcaster>,
+}
+
+impl Upcasters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `upcaster` to upgrade payloads of `event_type` from `from_version` to
+    /// `from_version + 1`.
+    pub fn register(
+        mut self,
+        event_type: impl Into<String>,
+        from_version: u32,
+        upcaster: impl Fn(Value) -> Value + Send + Sync + 'static,
+    ) -> Self {
+        self.upcasters
+            .insert((event_type.into(), from_version), Arc::new(upcaster));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upcasters.is_empty()
+    }
+
+    /// Applies every upcaster registered from `version` onwards to `payload`.
+    pub fn upcast(&self, event_type: &str, version: u32, mut payload: Value) -> Value {
+        let mut version = version;
+        while let Some(upcaster) = self.upcasters.get(&(event_type.to_string(), version)) {
+            payload = upcaster(payload);
+            version += 1;
+        }
+        payload
+    }
+
+    /// Deserializes a stored payload with the codec `codecs` holds for its content type. A payload
+    /// recorded at a schema version an upcaster is registered for is decoded to a `Value` and
+    /// upcast first; any other is decoded straight into `E`.
+    pub(crate) fn decode<E: Event>(
+        &self,
+        codecs: &Codecs,
+        event_type: &str,
+        metadata: &EventMetadata,
+        data: &[u8],
+    ) -> Result<E, Error> {
+        let version = metadata.schema_version().unwrap_or(INITIAL_SCHEMA_VERSION);
+        if !self
+            .upcasters
+            .contains_key(&(event_type.to_string(), version))
+        {
+            return codecs.decode(metadata.content_type(), data);
+        }
+        let payload = codecs.decode(metadata.content_type(), data)?;
+        let payload = self.upcast(event_type, version, payload);
+        Ok(serde_json::from_value(payload)?)
+    }
+}
+
+impl fmt::Debug for Upcasters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.upcasters.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn rename(from: &'static str, to: &'static str) -> impl Fn(Value) -> Value {
+        move |mut payload| {
+            if let Some(fields) = payload.as_object_mut()
+                && let Some(value) = fields.remove(from)
+            {
+                fields.insert(to.to_string(), value);
+            }
+            payload
+        }
+    }
+
+    #[test]
+    fn chains_upcasters_from_the_recorded_version() {
+        let upcasters = Upcasters::new()
+            .register("Renamed", 1, rename("a", "b"))
+            .register("Renamed", 2, rename("b", "c"));
+
+        assert_eq!(
+            upcasters.upcast("Renamed", 1, json!({ "a": 1 })),
+            json!({ "c": 1 })
+        );
+        assert_eq!(
+            upcasters.upcast("Renamed", 2, json!({ "b": 1 })),
+            json!({ "c": 1 })
+        );
+        assert_eq!(
+            upcasters.upcast("Renamed", 3, json!({ "c": 1 })),
+            json!({ "c": 1 })
+        );
+    }
+
+    #[test]
+    fn leaves_other_event_types_alone() {
+        let upcasters = Upcasters::new().register("Renamed", 1, rename("a", "b"));
+
+        assert_eq!(
+            upcasters.upcast("Other", 1, json!({ "a": 1 })),
+            json!({ "a": 1 })
+        );
+    }
+}
@@ -0,0 +1,127 @@
+mod test_cases;
+
+use mneme::{EventStore, EventStreamId, InMemoryEventStore};
+use test_cases::*;
+
+impl TestStore for InMemoryEventStore {
+    async fn create_test_store() -> Self {
+        InMemoryEventStore::new()
+    }
+
+    async fn read_client_events(event_store: &Self, stream_id: EventStreamId) -> Vec<TestEvent> {
+        let mut stream = event_store
+            .read_stream::<TestEvent>(stream_id)
+            .await
+            .expect("failed to read stream");
+        let mut events = vec![];
+        while let Some((event, _)) = stream.next().await.expect("failed to get next event") {
+            events.push(event);
+        }
+        events
+    }
+}
+
+#[tokio::test]
+async fn successful_command_execution_with_no_events_produced() {
+    test_successful_command_execution_with_no_events_produced::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn command_rejection_error() {
+    test_command_rejection_error::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn successful_execution_with_events_will_record_events() {
+    test_successful_execution_with_events_will_record_events::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn existing_events_are_available_to_handler() {
+    test_existing_events_are_available_to_handler::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn appends_check_expected_stream_state() {
+    test_appends_check_expected_stream_state::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn execute_reports_its_outcome() {
+    test_execute_reports_its_outcome::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn idempotent_appends_are_written_once() {
+    test_idempotent_appends_are_written_once::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn repeated_commands_return_the_original_outcome() {
+    test_repeated_commands_return_the_original_outcome::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn deleted_streams_are_recreated_by_appending() {
+    test_deleted_streams_are_recreated_by_appending::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn tombstoned_streams_cannot_be_used_again() {
+    test_tombstoned_streams_cannot_be_used_again::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn truncated_streams_skip_earlier_events() {
+    test_truncated_streams_skip_earlier_events::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn stream_metadata_is_versioned_and_limits_reads() {
+    test_stream_metadata_is_versioned_and_limits_reads::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn streams_read_backwards_and_from_a_position() {
+    test_streams_read_backwards_and_from_a_position::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn read_stream_after_returns_later_events() {
+    test_read_stream_after_returns_later_events::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn appended_events_keep_their_ids_and_metadata() {
+    test_appended_events_keep_their_ids_and_metadata::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn upcasts_events_recorded_at_older_schema_versions() {
+    test_upcasts_events_recorded_at_older_schema_versions::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn events_round_trip_through_their_codecs() {
+    test_events_round_trip_through_their_codecs::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn subscription_delivers_historical_then_live_events() {
+    test_subscription_delivers_historical_then_live_events::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn read_all_returns_events_across_streams_in_commit_order() {
+    test_read_all_returns_events_across_streams_in_commit_order::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn subscribe_to_all_resumes_after_position() {
+    test_subscribe_to_all_resumes_after_position::<InMemoryEventStore>().await
+}
+
+#[tokio::test]
+async fn projection_resumes_from_checkpoint() {
+    test_projection_resumes_from_checkpoint::<InMemoryEventStore>().await
+}
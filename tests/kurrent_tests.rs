@@ -1,10 +1,11 @@
 mod test_cases;
 
 use test_cases::*;
-use mneme::{ConnectionSettings, EventStreamId, Kurrent};
+use mneme::{ConnectionSettings, EventMetadata, EventStore, EventStreamId, Kurrent};
+use uuid::Uuid;
 
 impl TestStore for Kurrent {
-    fn create_test_store() -> Self {
+    async fn create_test_store() -> Self {
     let settings = ConnectionSettings::builder()
         .host("localhost")
         .port(2113)
@@ -55,3 +56,118 @@ async fn successful_execution_with_events_will_record_events() {
 async fn existing_events_are_available_to_handler() {
     test_existing_events_are_available_to_handler::<Kurrent>().await
 }
+
+#[tokio::test]
+async fn appends_check_expected_stream_state() {
+    test_appends_check_expected_stream_state::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn execute_reports_its_outcome() {
+    test_execute_reports_its_outcome::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn idempotent_appends_are_written_once() {
+    test_idempotent_appends_are_written_once::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn repeated_commands_return_the_original_outcome() {
+    test_repeated_commands_return_the_original_outcome::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn deleted_streams_are_recreated_by_appending() {
+    test_deleted_streams_are_recreated_by_appending::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn tombstoned_streams_cannot_be_used_again() {
+    test_tombstoned_streams_cannot_be_used_again::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn truncated_streams_skip_earlier_events() {
+    test_truncated_streams_skip_earlier_events::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn stream_metadata_is_versioned_and_limits_reads() {
+    test_stream_metadata_is_versioned_and_limits_reads::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn streams_read_backwards_and_from_a_position() {
+    test_streams_read_backwards_and_from_a_position::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn read_stream_after_returns_later_events() {
+    test_read_stream_after_returns_later_events::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn appended_events_keep_their_ids_and_metadata() {
+    test_appended_events_keep_their_ids_and_metadata::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn upcasts_events_recorded_at_older_schema_versions() {
+    test_upcasts_events_recorded_at_older_schema_versions::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn events_round_trip_through_their_codecs() {
+    test_events_round_trip_through_their_codecs::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn subscription_delivers_historical_then_live_events() {
+    test_subscription_delivers_historical_then_live_events::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn read_all_returns_events_across_streams_in_commit_order() {
+    test_read_all_returns_events_across_streams_in_commit_order::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn subscribe_to_all_resumes_after_position() {
+    test_subscribe_to_all_resumes_after_position::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn projection_resumes_from_checkpoint() {
+    test_projection_resumes_from_checkpoint::<Kurrent>().await
+}
+
+#[tokio::test]
+async fn stream_writer_records_metadata() {
+    let event_store = Kurrent::create_test_store().await;
+    let id = Uuid::new_v4();
+    let correlation_id = Uuid::new_v4();
+
+    event_store
+        .stream_writer(EventStreamId(id))
+        .metadata(
+            EventMetadata::new()
+                .with_correlation_id(correlation_id)
+                .with_value("tenant", "acme")
+                .unwrap(),
+        )
+        .append(vec![TestEvent::One { id }])
+        .await
+        .expect("Failed to append events");
+
+    let mut stream = event_store
+        .read_stream::<TestEvent>(EventStreamId(id))
+        .await
+        .expect("Failed to read stream");
+    let recorded = stream.next_recorded().await.unwrap().expect("event");
+    assert_eq!(recorded.metadata().correlation_id(), Some(correlation_id));
+    assert_eq!(
+        recorded.metadata().value("tenant"),
+        Some(&serde_json::json!("acme"))
+    );
+}
@@ -1,10 +1,18 @@
-use mneme::{AggregateState, Command, Error, Event, EventStore, EventStreamId, execute};
+use mneme::{
+    AggregateState, Cbor, Command, Error, Event, EventCodec, EventFilter, EventMetadata,
+    EventStore, EventStreamId, EventStreamVersion, ExecuteError, ExpectedVersion,
+    InMemoryCheckpointStore, MessagePack, NewEvent, Projection, ProjectionRunner, ProjectionStatus,
+    ReadAllOptions, ReadDirection, ReadStreamOptions, RecordedEvent, StreamAcl, StreamMetadata,
+    StreamPosition, Upcasters, execute,
+};
 use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
+use std::time::Duration;
 use uuid::Uuid;
 
 pub trait TestStore: EventStore + Send {
-    fn create_test_store() -> Self;
+    #[allow(async_fn_in_trait)]
+    async fn create_test_store() -> Self;
 
     #[allow(async_fn_in_trait)]
     async fn read_client_events(event_store: &Self, stream_id: EventStreamId) -> Vec<TestEvent>;
@@ -21,6 +29,12 @@ impl NoopCommand {
     }
 }
 
+impl Default for NoopCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Command for NoopCommand {
     type Event = ();
     type State = ();
@@ -51,6 +65,12 @@ impl RejectCommand {
     }
 }
 
+impl Default for RejectCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Command for RejectCommand {
     type Event = ();
     type State = ();
@@ -95,6 +115,42 @@ impl Command for EventProducingCommand {
     fn set_state(&mut self, _: &Self::State) {}
 }
 
+#[derive(Clone)]
+pub struct KeyedCommand {
+    id: Uuid,
+    key: String,
+}
+
+impl KeyedCommand {
+    pub fn new(id: Uuid, key: &str) -> Self {
+        Self {
+            id,
+            key: key.to_string(),
+        }
+    }
+}
+
+impl Command for KeyedCommand {
+    type Event = TestEvent;
+    type State = ();
+    type Error = Infallible;
+
+    fn handle(&self) -> Result<Vec<TestEvent>, Self::Error> {
+        Ok(vec![
+            TestEvent::One { id: self.id },
+            TestEvent::Two { id: self.id },
+        ])
+    }
+    fn event_stream_id(&self) -> EventStreamId {
+        EventStreamId(self.id)
+    }
+    fn get_state(&self) -> Self::State {}
+    fn set_state(&mut self, _: &Self::State) {}
+    fn idempotency_key(&self) -> Option<String> {
+        Some(self.key.clone())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct StatefulCommandState {
     foo: Option<u16>,
@@ -181,12 +237,12 @@ impl Event for TestEvent {
 }
 
 pub async fn test_successful_command_execution_with_no_events_produced<Adapter: TestStore>() {
-    let mut event_store: Adapter = TestStore::create_test_store();
+    let mut event_store = Adapter::create_test_store().await;
     let command = NoopCommand::new();
     let stream_id = command.event_stream_id();
 
     event_store
-        .publish(stream_id, vec![()], None)
+        .publish(stream_id, vec![()], ExpectedVersion::Any)
         .await
         .expect("Failed to publish");
 
@@ -195,36 +251,26 @@ pub async fn test_successful_command_execution_with_no_events_produced<Adapter:
 }
 
 pub async fn test_command_rejection_error<Adapter: TestStore>() {
-    let mut event_store: Adapter = TestStore::create_test_store();
+    let mut event_store = Adapter::create_test_store().await;
     let command = RejectCommand::new();
     let stream_id = command.event_stream_id();
 
     event_store
-        .publish(stream_id, vec![()], None)
+        .publish(stream_id, vec![()], ExpectedVersion::Any)
         .await
         .expect("Failed to publish");
 
     match execute(command, &mut event_store, Default::default()).await {
-        Err(Error::CommandFailed {
-            source,
-            message,
-            attempt: _,
-            max_attempts: _,
-        }) => {
-            if let Some(reject_error) = source.downcast_ref::<RejectCommandError>() {
-                assert_eq!(reject_error.to_string(), "Command failed: no");
-                assert_eq!(message, "Command failed: no");
-            } else {
-                panic!("Unexpected error type: {:?}", source);
-            }
+        Err(ExecuteError::Rejected(RejectCommandError(reason))) => {
+            assert_eq!(reason, "no");
         }
-        Ok(()) => panic!("Expected command to be rejected."),
+        Ok(_) => panic!("Expected command to be rejected."),
         Err(other) => panic!("Unexpected error: {:?}", other),
     }
 }
 
 pub async fn test_successful_execution_with_events_will_record_events<Adapter: TestStore>() {
-    let mut event_store: Adapter = TestStore::create_test_store();
+    let mut event_store = Adapter::create_test_store().await;
     let id = Uuid::new_v4();
     let command = EventProducingCommand::new(id);
 
@@ -237,7 +283,7 @@ pub async fn test_successful_execution_with_events_will_record_events<Adapter: T
 }
 
 pub async fn test_existing_events_are_available_to_handler<Adapter: TestStore>() {
-    let mut event_store: Adapter = TestStore::create_test_store();
+    let mut event_store = Adapter::create_test_store().await;
     let id = Uuid::new_v4();
     let rand_1: u16 = rand::random();
     let rand_2: u16 = rand::random();
@@ -249,13 +295,13 @@ pub async fn test_existing_events_are_available_to_handler<Adapter: TestStore>()
     ];
 
     event_store
-        .publish(EventStreamId(id), existing_events, None)
+        .publish(EventStreamId(id), existing_events, ExpectedVersion::Any)
         .await
         .unwrap();
 
     let command = StatefulCommand::new(id);
     match execute(command, &mut event_store, Default::default()).await {
-        Ok(()) => {
+        Ok(_) => {
             assert_eq!(
                 TestStore::read_client_events(&event_store, EventStreamId(id)).await,
                 vec![
@@ -268,3 +314,948 @@ pub async fn test_existing_events_are_available_to_handler<Adapter: TestStore>()
         other => panic!("Unexpected result: {:?}", other),
     };
 }
+
+pub async fn test_execute_reports_its_outcome<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![
+                TestEvent::FooHappened { id, value: 1 },
+                TestEvent::BarHappened { id, value: 2 },
+            ],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    let outcome = execute(
+        StatefulCommand::new(id),
+        &mut event_store,
+        Default::default(),
+    )
+    .await
+    .expect("failed to execute command");
+
+    assert_eq!(
+        outcome.events(),
+        &[(
+            TestEvent::BazHappened { id, value: 3 },
+            EventStreamVersion::new(2)
+        )]
+    );
+    assert_eq!(outcome.version(), Some(EventStreamVersion::new(2)));
+    assert_eq!(
+        outcome.position(),
+        event_store.head_position().await.unwrap()
+    );
+    assert_eq!(outcome.attempts(), 1);
+    assert_eq!(outcome.state().foo, Some(1));
+    assert_eq!(outcome.state().bar, Some(2));
+}
+
+pub async fn test_appends_check_expected_stream_state<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    let one = || vec![TestEvent::One { id }];
+
+    match event_store
+        .publish(EventStreamId(id), one(), ExpectedVersion::StreamExists)
+        .await
+    {
+        Err(Error::EventStoreVersionMismatch {
+            expected, actual, ..
+        }) => {
+            assert_eq!(expected, ExpectedVersion::StreamExists);
+            assert_eq!(actual, None);
+        }
+        other => panic!("Expected version mismatch, got: {:?}", other),
+    }
+
+    event_store
+        .publish(EventStreamId(id), one(), ExpectedVersion::NoStream)
+        .await
+        .expect("Failed to create stream");
+
+    match event_store
+        .publish(EventStreamId(id), one(), ExpectedVersion::NoStream)
+        .await
+    {
+        Err(Error::EventStoreVersionMismatch {
+            expected, actual, ..
+        }) => {
+            assert_eq!(expected, ExpectedVersion::NoStream);
+            assert_eq!(actual, Some(EventStreamVersion::new(0)));
+        }
+        other => panic!("Expected version mismatch, got: {:?}", other),
+    }
+
+    event_store
+        .publish(EventStreamId(id), one(), ExpectedVersion::StreamExists)
+        .await
+        .expect("Failed to append to existing stream");
+    event_store
+        .publish(
+            EventStreamId(id),
+            one(),
+            ExpectedVersion::Exact(EventStreamVersion::new(1)),
+        )
+        .await
+        .expect("Failed to append at expected version");
+    event_store
+        .publish(EventStreamId(id), one(), ExpectedVersion::Any)
+        .await
+        .expect("Failed to append at any version");
+}
+
+pub async fn test_idempotent_appends_are_written_once<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    let key = format!("deposit-{id}");
+    let events = || vec![TestEvent::One { id }, TestEvent::Two { id }];
+
+    let appended = event_store
+        .publish_idempotent(EventStreamId(id), events(), ExpectedVersion::NoStream, &key)
+        .await
+        .expect("failed to append events");
+    assert_eq!(appended.version(), Some(EventStreamVersion::new(1)));
+
+    for expected_version in [ExpectedVersion::NoStream, ExpectedVersion::Any] {
+        let replayed = event_store
+            .publish_idempotent(EventStreamId(id), events(), expected_version, &key)
+            .await
+            .expect("replaying the append should succeed");
+        assert_eq!(replayed, appended);
+    }
+    assert_eq!(
+        Adapter::read_client_events(&event_store, EventStreamId(id)).await,
+        events()
+    );
+
+    // The same events under another key are new events.
+    match event_store
+        .publish_idempotent(
+            EventStreamId(id),
+            events(),
+            ExpectedVersion::NoStream,
+            "another key",
+        )
+        .await
+    {
+        Err(Error::EventStoreVersionMismatch { .. }) => {}
+        other => panic!("expected a version mismatch, got {other:?}"),
+    }
+}
+
+pub async fn test_repeated_commands_return_the_original_outcome<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+
+    let original = execute(
+        KeyedCommand::new(id, "request-1"),
+        &mut event_store,
+        Default::default(),
+    )
+    .await
+    .expect("failed to execute command");
+    assert!(!original.is_duplicate());
+
+    execute(
+        KeyedCommand::new(id, "request-2"),
+        &mut event_store,
+        Default::default(),
+    )
+    .await
+    .expect("failed to execute command");
+
+    let repeated = execute(
+        KeyedCommand::new(id, "request-1"),
+        &mut event_store,
+        Default::default(),
+    )
+    .await
+    .expect("failed to execute command");
+
+    assert!(repeated.is_duplicate());
+    assert_eq!(repeated.attempts(), 0);
+    assert_eq!(repeated.events(), original.events());
+    assert_eq!(repeated.version(), Some(EventStreamVersion::new(1)));
+    assert_eq!(repeated.position(), original.position());
+    assert_eq!(
+        Adapter::read_client_events(&event_store, EventStreamId(id))
+            .await
+            .len(),
+        4
+    );
+}
+
+pub async fn test_deleted_streams_are_recreated_by_appending<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::One { id }, TestEvent::Two { id }],
+            ExpectedVersion::NoStream,
+        )
+        .await
+        .expect("failed to append events");
+
+    match event_store
+        .delete_stream(EventStreamId(id), ExpectedVersion::NoStream)
+        .await
+    {
+        Err(Error::EventStoreVersionMismatch { .. }) => {}
+        other => panic!("expected a version mismatch, got {other:?}"),
+    }
+    event_store
+        .delete_stream(
+            EventStreamId(id),
+            ExpectedVersion::Exact(EventStreamVersion::new(1)),
+        )
+        .await
+        .expect("failed to delete stream");
+    match event_store
+        .read_stream::<TestEvent>(EventStreamId(id))
+        .await
+    {
+        Err(Error::EventStoreStreamNotFound(_)) => {}
+        Err(e) => panic!("expected the stream to be gone, got {e:?}"),
+        Ok(_) => panic!("expected the stream to be gone"),
+    }
+
+    // A recreated stream carries on from the version it was deleted at, without its old events.
+    let appended = event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::Two { id }],
+            ExpectedVersion::NoStream,
+        )
+        .await
+        .expect("failed to recreate stream");
+    assert_eq!(appended.version(), Some(EventStreamVersion::new(2)));
+    assert_eq!(
+        Adapter::read_client_events(&event_store, EventStreamId(id)).await,
+        vec![TestEvent::Two { id }]
+    );
+}
+
+pub async fn test_tombstoned_streams_cannot_be_used_again<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::One { id }],
+            ExpectedVersion::NoStream,
+        )
+        .await
+        .expect("failed to append events");
+
+    event_store
+        .tombstone_stream(EventStreamId(id), ExpectedVersion::StreamExists)
+        .await
+        .expect("failed to tombstone stream");
+
+    match event_store
+        .read_stream::<TestEvent>(EventStreamId(id))
+        .await
+    {
+        Err(Error::EventStoreStreamDeleted(_)) => {}
+        Err(e) => panic!("expected the stream to be deleted, got {e:?}"),
+        Ok(_) => panic!("expected the stream to be deleted"),
+    }
+    match event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::Two { id }],
+            ExpectedVersion::Any,
+        )
+        .await
+    {
+        Err(Error::EventStoreStreamDeleted(_)) => {}
+        other => panic!("expected the stream to be deleted, got {other:?}"),
+    }
+    match event_store
+        .delete_stream(EventStreamId(id), ExpectedVersion::Any)
+        .await
+    {
+        Err(Error::EventStoreStreamDeleted(_)) => {}
+        other => panic!("expected the stream to be deleted, got {other:?}"),
+    }
+}
+
+pub async fn test_truncated_streams_skip_earlier_events<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![
+                TestEvent::One { id },
+                TestEvent::Two { id },
+                TestEvent::One { id },
+            ],
+            ExpectedVersion::NoStream,
+        )
+        .await
+        .expect("failed to append events");
+
+    event_store
+        .truncate_stream(EventStreamId(id), EventStreamVersion::new(2))
+        .await
+        .expect("failed to truncate stream");
+
+    let mut stream = event_store
+        .read_stream::<TestEvent>(EventStreamId(id))
+        .await
+        .expect("failed to read stream");
+    let first = stream
+        .next_recorded()
+        .await
+        .expect("failed to read event")
+        .expect("expected an event after the truncation");
+    assert_eq!(first.version(), EventStreamVersion::new(2));
+    assert!(stream.next_recorded().await.unwrap().is_none());
+
+    // Truncation doesn't change the stream's version.
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::Two { id }],
+            ExpectedVersion::Exact(EventStreamVersion::new(2)),
+        )
+        .await
+        .expect("failed to append after truncating");
+}
+
+pub async fn test_stream_metadata_is_versioned_and_limits_reads<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+
+    let (metadata, version) = event_store
+        .read_stream_metadata(EventStreamId(id))
+        .await
+        .expect("failed to read metadata");
+    assert_eq!(metadata, StreamMetadata::new());
+    assert_eq!(version, None);
+
+    let metadata = StreamMetadata::new()
+        .with_max_count(2)
+        .with_acl(StreamAcl::new().with_read_role("auditors"))
+        .with_value("owner", "billing")
+        .expect("failed to add a metadata value");
+    let version = event_store
+        .set_stream_metadata(EventStreamId(id), metadata.clone(), version.into())
+        .await
+        .expect("failed to write metadata");
+    assert_eq!(
+        event_store
+            .read_stream_metadata(EventStreamId(id))
+            .await
+            .expect("failed to read metadata"),
+        (metadata.clone(), Some(version))
+    );
+
+    match event_store
+        .set_stream_metadata(EventStreamId(id), metadata, ExpectedVersion::NoStream)
+        .await
+    {
+        Err(Error::EventStoreVersionMismatch { .. }) => {}
+        other => panic!("expected a version mismatch, got {other:?}"),
+    }
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![
+                TestEvent::One { id },
+                TestEvent::Two { id },
+                TestEvent::One { id },
+            ],
+            ExpectedVersion::NoStream,
+        )
+        .await
+        .expect("failed to append events");
+    assert_eq!(
+        Adapter::read_client_events(&event_store, EventStreamId(id)).await,
+        vec![TestEvent::Two { id }, TestEvent::One { id }]
+    );
+
+    let mut stream = event_store
+        .read_stream_with::<TestEvent>(
+            EventStreamId(id),
+            ReadStreamOptions::new().backwards().max_count(3),
+        )
+        .await
+        .expect("failed to read stream");
+    let mut versions = Vec::new();
+    while let Some((_, version)) = stream.next().await.expect("failed to read event") {
+        versions.push(version.value());
+    }
+    assert_eq!(versions, [2, 1]);
+}
+
+pub async fn test_streams_read_backwards_and_from_a_position<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![
+                TestEvent::One { id },
+                TestEvent::Two { id },
+                TestEvent::One { id },
+                TestEvent::Two { id },
+            ],
+            ExpectedVersion::NoStream,
+        )
+        .await
+        .expect("failed to append events");
+
+    let read = |options: ReadStreamOptions| {
+        let event_store = &event_store;
+        async move {
+            let mut stream = event_store
+                .read_stream_with::<TestEvent>(EventStreamId(id), options)
+                .await
+                .expect("failed to read stream");
+            let mut versions = Vec::new();
+            while let Some((_, version)) = stream.next().await.expect("failed to read event") {
+                versions.push(version.value());
+            }
+            versions
+        }
+    };
+    assert_eq!(read(ReadStreamOptions::new()).await, [0, 1, 2, 3]);
+    assert_eq!(
+        read(ReadStreamOptions::new().backwards().max_count(2)).await,
+        [3, 2]
+    );
+    assert_eq!(
+        read(
+            ReadStreamOptions::new()
+                .direction(ReadDirection::Backwards)
+                .position(StreamPosition::Version(EventStreamVersion::new(1)))
+        )
+        .await,
+        [1, 0]
+    );
+    assert_eq!(
+        read(
+            ReadStreamOptions::new()
+                .position(StreamPosition::Version(EventStreamVersion::new(2)))
+                .max_count(1)
+        )
+        .await,
+        [2]
+    );
+
+    let last = event_store
+        .read_last::<TestEvent>(EventStreamId(id))
+        .await
+        .expect("failed to read last event")
+        .expect("expected a last event");
+    assert_eq!(last.version(), EventStreamVersion::new(3));
+    assert_eq!(last.event(), &TestEvent::Two { id });
+}
+
+pub async fn test_read_stream_after_returns_later_events<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![
+                TestEvent::One { id },
+                TestEvent::Two { id },
+                TestEvent::FooHappened { id, value: 1 },
+            ],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    let mut stream = event_store
+        .read_stream_after::<TestEvent>(EventStreamId(id), EventStreamVersion::new(0))
+        .await
+        .expect("Failed to read stream");
+    let mut events = vec![];
+    while let Some(event) = stream.next().await.expect("Failed to read event") {
+        events.push(event);
+    }
+    assert_eq!(
+        events,
+        vec![
+            (TestEvent::Two { id }, EventStreamVersion::new(1)),
+            (
+                TestEvent::FooHappened { id, value: 1 },
+                EventStreamVersion::new(2)
+            ),
+        ]
+    );
+}
+
+pub async fn test_appended_events_keep_their_ids_and_metadata<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    let event_id = Uuid::new_v4();
+    let correlation_id = Uuid::new_v4();
+    let causation_id = Uuid::new_v4();
+    let before = chrono::Utc::now();
+
+    event_store
+        .append(
+            EventStreamId(id),
+            vec![
+                NewEvent::new(TestEvent::One { id })
+                    .with_id(event_id)
+                    .with_metadata(
+                        EventMetadata::new()
+                            .with_correlation_id(correlation_id)
+                            .with_causation_id(causation_id)
+                            .with_value("tenant", "acme")
+                            .unwrap(),
+                    ),
+                NewEvent::new(TestEvent::Two { id }),
+            ],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    let mut stream = event_store
+        .read_stream::<TestEvent>(EventStreamId(id))
+        .await
+        .expect("Failed to read stream");
+    let first = stream.next_recorded().await.unwrap().expect("first event");
+    let second = stream.next_recorded().await.unwrap().expect("second event");
+
+    assert_eq!(first.id(), event_id);
+    assert_eq!(first.event_type(), "TestEvent.One");
+    assert_eq!(first.metadata().correlation_id(), Some(correlation_id));
+    assert_eq!(first.metadata().causation_id(), Some(causation_id));
+    assert_eq!(
+        first.metadata().value("tenant"),
+        Some(&serde_json::json!("acme"))
+    );
+    let slack = chrono::Duration::seconds(5);
+    assert!(first.created() > before - slack && first.created() < chrono::Utc::now() + slack);
+
+    assert_ne!(second.id(), event_id);
+    assert_eq!(second.metadata().correlation_id(), None);
+    assert_eq!(second.metadata().schema_version(), Some(1));
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+enum NoteV1 {
+    Noted { text: String },
+}
+
+impl Event for NoteV1 {
+    fn event_type(&self) -> String {
+        "Note.Noted".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+enum Note {
+    Noted { body: String, pinned: bool },
+}
+
+impl Event for Note {
+    fn event_type(&self) -> String {
+        "Note.Noted".to_string()
+    }
+
+    fn schema_version(&self) -> u32 {
+        2
+    }
+}
+
+pub async fn test_upcasts_events_recorded_at_older_schema_versions<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![NoteV1::Noted {
+                text: "old".to_string(),
+            }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![Note::Noted {
+                body: "new".to_string(),
+                pinned: true,
+            }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    let upcasters = Upcasters::new().register("Note.Noted", 1, |mut payload| {
+        let noted = &mut payload["Noted"];
+        noted["body"] = noted["text"].take();
+        noted["pinned"] = false.into();
+        payload
+    });
+    let mut stream = event_store
+        .read_stream::<Note>(EventStreamId(id))
+        .await
+        .expect("Failed to read stream")
+        .with_upcasters(upcasters);
+    let mut events = vec![];
+    while let Some((event, _)) = stream.next().await.expect("Failed to read event") {
+        events.push(event);
+    }
+    assert_eq!(
+        events,
+        vec![
+            Note::Noted {
+                body: "old".to_string(),
+                pinned: false,
+            },
+            Note::Noted {
+                body: "new".to_string(),
+                pinned: true,
+            },
+        ]
+    );
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+enum Telemetry {
+    Packed { sensor: String, value: f64 },
+    Concise { sensor: String, value: f64 },
+}
+
+impl Event for Telemetry {
+    fn event_type(&self) -> String {
+        match self {
+            Telemetry::Packed { .. } => "Telemetry.Packed".to_string(),
+            Telemetry::Concise { .. } => "Telemetry.Concise".to_string(),
+        }
+    }
+
+    fn codec(&self) -> Option<&'static dyn EventCodec> {
+        match self {
+            Telemetry::Packed { .. } => Some(&MessagePack),
+            Telemetry::Concise { .. } => Some(&Cbor),
+        }
+    }
+}
+
+pub async fn test_events_round_trip_through_their_codecs<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+    let events = vec![
+        Telemetry::Packed {
+            sensor: "t-1".to_string(),
+            value: 21.5,
+        },
+        Telemetry::Concise {
+            sensor: "t-2".to_string(),
+            value: -3.25,
+        },
+    ];
+    event_store
+        .publish(EventStreamId(id), events.clone(), ExpectedVersion::Any)
+        .await
+        .unwrap();
+
+    let mut stream = event_store
+        .read_stream::<Telemetry>(EventStreamId(id))
+        .await
+        .expect("Failed to read stream");
+    let mut read = vec![];
+    while let Some(recorded) = stream.next_recorded().await.expect("Failed to read event") {
+        read.push((
+            recorded.metadata().content_type().map(str::to_string),
+            recorded.into_event(),
+        ));
+    }
+    assert_eq!(
+        read,
+        vec![
+            (Some("application/msgpack".to_string()), events[0].clone()),
+            (Some("application/cbor".to_string()), events[1].clone()),
+        ]
+    );
+}
+
+pub async fn test_subscription_delivers_historical_then_live_events<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::One { id }, TestEvent::Two { id }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    let mut from_start = event_store
+        .subscribe_to_stream::<TestEvent>(EventStreamId(id), None)
+        .await
+        .expect("Failed to subscribe");
+    let mut from_version = event_store
+        .subscribe_to_stream::<TestEvent>(EventStreamId(id), Some(EventStreamVersion::new(0)))
+        .await
+        .expect("Failed to subscribe");
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::FooHappened { id, value: 1 }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    let mut received = vec![];
+    for _ in 0..3 {
+        received.push(next_within_timeout(&mut from_start).await);
+    }
+    assert_eq!(
+        received,
+        vec![
+            (TestEvent::One { id }, EventStreamVersion::new(0)),
+            (TestEvent::Two { id }, EventStreamVersion::new(1)),
+            (
+                TestEvent::FooHappened { id, value: 1 },
+                EventStreamVersion::new(2)
+            ),
+        ]
+    );
+
+    assert_eq!(
+        next_within_timeout(&mut from_version).await,
+        (TestEvent::Two { id }, EventStreamVersion::new(1))
+    );
+}
+
+pub async fn test_read_all_returns_events_across_streams_in_commit_order<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let first = Uuid::new_v4();
+    let second = Uuid::new_v4();
+
+    for (id, events) in [
+        (first, vec![TestEvent::One { id: first }]),
+        (
+            second,
+            vec![TestEvent::One { id: second }, TestEvent::Two { id: second }],
+        ),
+        (first, vec![TestEvent::Two { id: first }]),
+    ] {
+        event_store
+            .publish(EventStreamId(id), events, ExpectedVersion::Any)
+            .await
+            .unwrap();
+    }
+
+    // Other tests may share the store, so only look at the streams written here.
+    let options = ReadAllOptions::new().filter(EventFilter::stream_prefix([
+        first.to_string(),
+        second.to_string(),
+    ]));
+    let recorded = read_all_events(&event_store, options.clone()).await;
+    assert_eq!(
+        recorded
+            .iter()
+            .map(|r| (r.stream_id().clone(), r.version(), r.event().clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                EventStreamId(first),
+                EventStreamVersion::new(0),
+                TestEvent::One { id: first }
+            ),
+            (
+                EventStreamId(second),
+                EventStreamVersion::new(0),
+                TestEvent::One { id: second }
+            ),
+            (
+                EventStreamId(second),
+                EventStreamVersion::new(1),
+                TestEvent::Two { id: second }
+            ),
+            (
+                EventStreamId(first),
+                EventStreamVersion::new(1),
+                TestEvent::Two { id: first }
+            ),
+        ]
+    );
+    assert!(
+        recorded
+            .windows(2)
+            .all(|w| w[0].position() < w[1].position())
+    );
+
+    let resumed =
+        read_all_events(&event_store, options.clone().after(recorded[1].position())).await;
+    assert_eq!(resumed, recorded[2..]);
+
+    let limited = read_all_events(&event_store, options.max_count(1)).await;
+    assert_eq!(limited, recorded[..1]);
+}
+
+pub async fn test_subscribe_to_all_resumes_after_position<Adapter: TestStore>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let id = Uuid::new_v4();
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::One { id }, TestEvent::Two { id }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    let options = ReadAllOptions::new().filter(EventFilter::stream_prefix([id.to_string()]));
+    let recorded = read_all_events(&event_store, options.clone()).await;
+
+    let mut subscription = event_store
+        .subscribe_to_all::<TestEvent>(options.after(recorded[0].position()))
+        .await
+        .expect("Failed to subscribe");
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::FooHappened { id, value: 1 }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        next_recorded_within_timeout(&mut subscription).await,
+        recorded[1]
+    );
+    let live = next_recorded_within_timeout(&mut subscription).await;
+    assert_eq!(live.event(), &TestEvent::FooHappened { id, value: 1 });
+    assert!(live.position() > recorded[1].position());
+}
+
+struct CollectingProjection {
+    stream_id: Uuid,
+    events: Vec<TestEvent>,
+}
+
+impl Projection for CollectingProjection {
+    type Event = TestEvent;
+    type Error = Infallible;
+
+    fn name(&self) -> String {
+        format!("collect-{}", self.stream_id)
+    }
+
+    fn filter(&self) -> Option<EventFilter> {
+        Some(EventFilter::stream_prefix([self.stream_id.to_string()]))
+    }
+
+    async fn handle(&mut self, event: &RecordedEvent<TestEvent>) -> Result<(), Infallible> {
+        self.events.push(event.event().clone());
+        Ok(())
+    }
+}
+
+pub async fn test_projection_resumes_from_checkpoint<Adapter: TestStore + Clone + Sync>() {
+    let mut event_store = Adapter::create_test_store().await;
+    let checkpoints = InMemoryCheckpointStore::new();
+    let id = Uuid::new_v4();
+
+    let run_to_end = |event_store: Adapter, checkpoints: InMemoryCheckpointStore| async move {
+        let runner = ProjectionRunner::new(
+            event_store,
+            CollectingProjection {
+                stream_id: id,
+                events: vec![],
+            },
+            checkpoints,
+        )
+        .with_batch_size(1)
+        .unwrap();
+        let mut status = runner.status();
+        let caught_up = async move {
+            let _ = status.wait_for(ProjectionStatus::is_caught_up).await;
+        };
+        tokio::time::timeout(Duration::from_secs(10), runner.run_until(caught_up))
+            .await
+            .expect("timed out waiting for projection to catch up")
+            .expect("projection failed")
+            .events
+    };
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::One { id }, TestEvent::Two { id }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        run_to_end(event_store.clone(), checkpoints.clone()).await,
+        vec![TestEvent::One { id }, TestEvent::Two { id }]
+    );
+
+    event_store
+        .publish(
+            EventStreamId(id),
+            vec![TestEvent::FooHappened { id, value: 1 }],
+            ExpectedVersion::Any,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        run_to_end(event_store.clone(), checkpoints).await,
+        vec![TestEvent::FooHappened { id, value: 1 }]
+    );
+}
+
+async fn read_all_events<S: EventStore>(
+    event_store: &S,
+    options: ReadAllOptions,
+) -> Vec<RecordedEvent<TestEvent>> {
+    let mut stream = event_store
+        .read_all::<TestEvent>(options)
+        .await
+        .expect("Failed to read all streams");
+    let mut events = vec![];
+    while let Some(event) = stream.next_recorded().await.expect("Failed to read event") {
+        events.push(event);
+    }
+    events
+}
+
+async fn next_recorded_within_timeout(
+    subscription: &mut mneme::Subscription<TestEvent>,
+) -> RecordedEvent<TestEvent> {
+    tokio::time::timeout(Duration::from_secs(5), subscription.next_recorded())
+        .await
+        .expect("timed out waiting for subscription event")
+        .expect("subscription failed")
+}
+
+async fn next_within_timeout(
+    subscription: &mut mneme::Subscription<TestEvent>,
+) -> (TestEvent, EventStreamVersion) {
+    tokio::time::timeout(Duration::from_secs(5), subscription.next())
+        .await
+        .expect("timed out waiting for subscription event")
+        .expect("subscription failed")
+}